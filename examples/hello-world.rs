@@ -4,7 +4,8 @@ use std::num::NonZeroU32;
 use glam::vec2;
 use glutin::surface::GlSurface;
 use loki_draw::drawer::{Drawer, RectBlueprint, TextBlueprint};
-use loki_draw::font::Font;
+use loki_draw::font::{Font, FontId, FontQuery, FontRegistry, FontStack};
+use loki_draw::layout::LayoutOptions;
 use loki_draw::rect::Rect;
 use loki_draw::OpenglDrawer;
 use opengl::{create_opengl_window, OpenglCtx};
@@ -14,6 +15,21 @@ use winit::keyboard::{KeyCode, PhysicalKey};
 
 const ROBOTO_FONT: &[u8] = include_bytes!("common/Roboto-Regular.ttf");
 
+/// System font families drawing the characters Roboto lacks, tried in
+/// order before any other system font.
+const FALLBACK_FAMILIES: &[&str] = &[
+    "Noto Sans CJK JP",
+    "Noto Sans JP",
+    "Source Han Sans JP",
+    "Hiragino Sans",
+    "Yu Gothic",
+    "Microsoft YaHei",
+    "PingFang SC",
+    "Noto Sans Symbols 2",
+    "Segoe UI Symbol",
+    "DejaVu Sans",
+];
+
 #[path = "common/opengl.rs"]
 mod opengl;
 
@@ -28,7 +44,19 @@ fn main() -> Result<(), Box<dyn Error>> {
         window,
     } = create_opengl_window(width, height)?;
 
-    let default_font = Font::from_data(ROBOTO_FONT);
+    let mut fonts = FontRegistry::new();
+    let default_font = fonts.add(Font::from_data(ROBOTO_FONT));
+    fonts.add_system_fonts();
+
+    let mut fallbacks: Vec<FontId> = FALLBACK_FAMILIES
+        .iter()
+        .filter_map(|&family| fonts.find(&FontQuery::new(family)))
+        .collect();
+    let others: Vec<FontId> = fonts
+        .ids()
+        .filter(|id| *id != default_font && !fallbacks.contains(id))
+        .collect();
+    fallbacks.extend(others);
 
     let mut drawer = OpenglDrawer::new(width, height, 1.);
     let mut viewport = vec2(width as f32, height as f32);
@@ -67,10 +95,13 @@ fn main() -> Result<(), Box<dyn Error>> {
                             text: "Hello world! 大大大大大好きな君へ♡",
                             x: 20.,
                             y: 50.,
-                            font: &default_font,
+                            font: FontStack::from_registry(&fonts, default_font, &fallbacks),
                             size: 50.,
                             col: 0xffffff,
                             alpha: 1.,
+                            outline: None,
+                            shadow: None,
+                            layout: LayoutOptions::default(),
                         };
 
                         text.x = (viewport.x - text.text_width()) / 2.0;
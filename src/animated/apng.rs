@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use png::{BlendOp, ColorType, Decoder, DisposeOp, Transformations};
+
+use super::{AnimatedImage, AnimatedImageError, AnimationFrame, Canvas, DEFAULT_DELAY};
+use crate::image_data::{ImageData, PixelFormat};
+
+impl From<png::DecodingError> for AnimatedImageError {
+    fn from(err: png::DecodingError) -> Self {
+        Self::Png(err)
+    }
+}
+
+impl AnimatedImage {
+    /// Decode all frames of an APNG image.
+    ///
+    /// A PNG image that isn't animated gives a single frame.
+    pub fn from_apng(data: &[u8]) -> Result<Self, AnimatedImageError> {
+        let mut decoder = Decoder::new(data);
+        decoder.set_transformations(Transformations::normalize_to_color8());
+
+        let mut reader = decoder.read_info()?;
+        let (width, height) = (reader.info().width, reader.info().height);
+        let mut buf = vec![0; reader.output_buffer_size()];
+
+        let Some(animation) = reader.info().animation_control else {
+            let output = reader.next_frame(&mut buf)?;
+            let pixels = to_rgba(&output, &buf);
+            let frame = AnimationFrame {
+                pixels,
+                delay: Duration::ZERO,
+            };
+
+            return Self::from_frames(width, height, vec![frame]);
+        };
+
+        // The default image is only part of the animation if it has a frame control chunk.
+        if reader.info().frame_control.is_none() {
+            reader.next_frame(&mut buf)?;
+        }
+
+        let mut canvas = Canvas::new(width, height);
+        let mut frames = Vec::new();
+
+        for _ in 0..animation.num_frames {
+            let output = reader.next_frame(&mut buf)?;
+            let control = *reader.info().frame_control().unwrap();
+            let (x, y) = (control.x_offset, control.y_offset);
+            let (w, h) = (control.width, control.height);
+            let previous =
+                (control.dispose_op == DisposeOp::Previous).then(|| canvas.pixels.clone());
+
+            let pixels = to_rgba(&output, &buf);
+            canvas.draw(x, y, w, h, &pixels, control.blend_op == BlendOp::Over);
+
+            let den = match control.delay_den {
+                0 => 100,
+                den => den,
+            };
+
+            let delay = match control.delay_num {
+                0 => DEFAULT_DELAY,
+                num => Duration::from_secs_f64(num as f64 / den as f64),
+            };
+
+            frames.push(AnimationFrame {
+                pixels: canvas.pixels.clone(),
+                delay,
+            });
+
+            match control.dispose_op {
+                DisposeOp::Background => canvas.clear(x, y, w, h),
+                DisposeOp::Previous => canvas.pixels = previous.unwrap(),
+                DisposeOp::None => (),
+            }
+        }
+
+        Self::from_frames(width, height, frames)
+    }
+}
+
+/// Convert a decoded frame to tightly packed RGBA.
+fn to_rgba(output: &png::OutputInfo, buf: &[u8]) -> Vec<u8> {
+    let format = match output.color_type {
+        ColorType::Grayscale => PixelFormat::Gray8,
+        ColorType::GrayscaleAlpha => PixelFormat::GrayAlpha8,
+        ColorType::Rgb | ColorType::Indexed => PixelFormat::Rgb8,
+        ColorType::Rgba => PixelFormat::Rgba8,
+    };
+
+    ImageData::new(format, output.width, output.height, buf)
+        .with_stride(output.line_size as u32)
+        .to_rgba(false)
+}
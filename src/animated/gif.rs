@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use gif::{ColorOutput, DecodeOptions, DisposalMethod};
+
+use super::{AnimatedImage, AnimatedImageError, AnimationFrame, Canvas, DEFAULT_DELAY};
+
+impl From<gif::DecodingError> for AnimatedImageError {
+    fn from(err: gif::DecodingError) -> Self {
+        Self::Gif(err)
+    }
+}
+
+impl AnimatedImage {
+    /// Decode all frames of a GIF image.
+    pub fn from_gif(data: &[u8]) -> Result<Self, AnimatedImageError> {
+        let mut options = DecodeOptions::new();
+        options.set_color_output(ColorOutput::RGBA);
+
+        let mut decoder = options.read_info(data)?;
+        let (width, height) = (decoder.width() as u32, decoder.height() as u32);
+        let mut canvas = Canvas::new(width, height);
+        let mut frames = Vec::new();
+
+        while let Some(frame) = decoder.read_next_frame()? {
+            let (x, y) = (frame.left as u32, frame.top as u32);
+            let (w, h) = (frame.width as u32, frame.height as u32);
+            let previous =
+                (frame.dispose == DisposalMethod::Previous).then(|| canvas.pixels.clone());
+
+            // Transparent pixels are fully transparent, so blending leaves them out.
+            canvas.draw(x, y, w, h, &frame.buffer, true);
+
+            // Delays are in hundredths of a second, and tiny ones are ignored by browsers.
+            let delay = match frame.delay {
+                0 | 1 => DEFAULT_DELAY,
+                delay => Duration::from_millis(delay as u64 * 10),
+            };
+
+            frames.push(AnimationFrame {
+                pixels: canvas.pixels.clone(),
+                delay,
+            });
+
+            match frame.dispose {
+                DisposalMethod::Background => canvas.clear(x, y, w, h),
+                DisposalMethod::Previous => canvas.pixels = previous.unwrap(),
+                DisposalMethod::Any | DisposalMethod::Keep => (),
+            }
+        }
+
+        Self::from_frames(width, height, frames)
+    }
+}
@@ -0,0 +1,242 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::atlas::ImageAtlas;
+use crate::drawer::{Drawer, SubImage};
+use crate::image_data::ImageData;
+
+#[cfg(feature = "apng")]
+mod apng;
+#[cfg(feature = "gif")]
+mod gif;
+#[cfg(feature = "webp")]
+mod webp;
+
+/// Delay used for frames that don't specify a usable one, like browsers do.
+#[cfg(any(feature = "gif", feature = "apng"))]
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+/// A fully composed frame of an animation.
+pub struct AnimationFrame {
+    /// Tightly packed RGBA pixels covering the whole animation.
+    pub pixels: Vec<u8>,
+    /// How long the frame stays on screen.
+    pub delay: Duration,
+}
+
+#[derive(Debug)]
+pub enum AnimatedImageError {
+    #[cfg(feature = "gif")]
+    Gif(::gif::DecodingError),
+    #[cfg(feature = "apng")]
+    Png(::png::DecodingError),
+    #[cfg(feature = "webp")]
+    WebP(::image_webp::DecodingError),
+    /// The image doesn't contain any frame.
+    NoFrames,
+}
+
+impl fmt::Display for AnimatedImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "gif")]
+            Self::Gif(err) => write!(f, "Could not decode GIF: {err}"),
+            #[cfg(feature = "apng")]
+            Self::Png(err) => write!(f, "Could not decode APNG: {err}"),
+            #[cfg(feature = "webp")]
+            Self::WebP(err) => write!(f, "Could not decode WebP: {err}"),
+            Self::NoFrames => write!(f, "Animated image has no frames"),
+        }
+    }
+}
+
+impl std::error::Error for AnimatedImageError {}
+
+/// An animated image, such as an animated sticker or a reaction GIF.
+///
+/// All frames are decoded up front, but they are only uploaded to the
+/// drawer the first time they are shown. Frames are packed in an
+/// [`ImageAtlas`], so small animations share a few textures.
+///
+/// Decoders are enabled with the `gif`, `apng` and `webp` features.
+pub struct AnimatedImage {
+    width: u32,
+    height: u32,
+    frames: Vec<AnimationFrame>,
+    /// Time at which each frame ends, from the start of the animation.
+    frame_ends: Vec<Duration>,
+    uploaded: Vec<Option<SubImage>>,
+    atlas: ImageAtlas,
+}
+
+impl AnimatedImage {
+    /// Create an animation from already decoded frames.
+    pub fn from_frames(
+        width: u32,
+        height: u32,
+        frames: Vec<AnimationFrame>,
+    ) -> Result<Self, AnimatedImageError> {
+        if frames.is_empty() {
+            return Err(AnimatedImageError::NoFrames);
+        }
+
+        let frame_ends = frames
+            .iter()
+            .scan(Duration::ZERO, |end, frame| {
+                *end += frame.delay;
+                Some(*end)
+            })
+            .collect();
+
+        // Leave room for the padding of the atlas around the frame.
+        let page_size = (width.max(height) + 2).next_power_of_two().max(512);
+
+        Ok(Self {
+            width,
+            height,
+            uploaded: vec![None; frames.len()],
+            frames,
+            frame_ends,
+            atlas: ImageAtlas::new(page_size),
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Duration of one loop of the animation.
+    pub fn duration(&self) -> Duration {
+        *self.frame_ends.last().unwrap()
+    }
+
+    /// Index of the frame shown `elapsed` after the animation started.
+    ///
+    /// The animation loops forever.
+    pub fn frame_index_at(&self, elapsed: Duration) -> usize {
+        let duration = self.duration();
+        if duration.is_zero() {
+            return 0;
+        }
+
+        let elapsed = Duration::from_nanos((elapsed.as_nanos() % duration.as_nanos()) as u64);
+        self.frame_ends.partition_point(|&end| end <= elapsed)
+    }
+
+    /// Get the frame to draw `elapsed` after the animation started.
+    ///
+    /// The frame is uploaded to the drawer if it wasn't already.
+    pub fn frame_at(&mut self, drawer: &mut dyn Drawer, elapsed: Duration) -> SubImage {
+        let index = self.frame_index_at(elapsed);
+
+        if let Some(image) = self.uploaded[index] {
+            return image;
+        }
+
+        let frame = &mut self.frames[index];
+        let data = ImageData::rgba(self.width, self.height, &frame.pixels);
+        let image = self.atlas.insert(drawer, &data).unwrap();
+
+        // The pixels live on the drawer side from now on.
+        frame.pixels = Vec::new();
+        self.uploaded[index] = Some(image);
+        image
+    }
+
+    /// Free the frames uploaded to the drawer right away, instead of at the
+    /// end of the frame as when dropped.
+    pub fn destroy(self, drawer: &mut dyn Drawer) {
+        self.atlas.destroy(drawer);
+    }
+}
+
+/// Composes the frames of a GIF or APNG animation, which only store the
+/// region of the canvas that changes from one frame to the next.
+#[cfg(any(feature = "gif", feature = "apng"))]
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+#[cfg(any(feature = "gif", feature = "apng"))]
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; (width * height * 4) as usize],
+        }
+    }
+
+    /// Iterate over the canvas rows covered by a region, clipped to the canvas.
+    fn rows(
+        &self,
+        x: u32,
+        y: u32,
+        w: u32,
+        h: u32,
+    ) -> impl Iterator<Item = (u32, std::ops::Range<usize>)> {
+        let (canvas_w, canvas_h) = (self.width, self.height);
+        let x_end = (x + w).min(canvas_w);
+
+        (y..(y + h).min(canvas_h))
+            .filter(move |_| x < x_end)
+            .map(move |row| {
+                let start = ((row * canvas_w + x) * 4) as usize;
+                let end = ((row * canvas_w + x_end) * 4) as usize;
+                (row - y, start..end)
+            })
+    }
+
+    /// Draw RGBA pixels of a `w` by `h` region at `x` and `y`.
+    ///
+    /// If `blend` is `true`, the pixels are composited over the canvas,
+    /// otherwise they replace it.
+    fn draw(&mut self, x: u32, y: u32, w: u32, h: u32, rgba: &[u8], blend: bool) {
+        let rows: Vec<_> = self.rows(x, y, w, h).collect();
+
+        for (src_row, range) in rows {
+            let src_start = (src_row * w * 4) as usize;
+            let src = &rgba[src_start..src_start + range.len()];
+            let dst = &mut self.pixels[range];
+
+            if !blend {
+                dst.copy_from_slice(src);
+                continue;
+            }
+
+            for (d, s) in dst.chunks_exact_mut(4).zip(src.chunks_exact(4)) {
+                let (sa, da) = (s[3] as f32 / 255., d[3] as f32 / 255.);
+                let out_a = sa + da * (1. - sa);
+                if out_a <= 0. {
+                    d.copy_from_slice(&[0; 4]);
+                    continue;
+                }
+
+                for c in 0..3 {
+                    let out = (s[c] as f32 * sa + d[c] as f32 * da * (1. - sa)) / out_a;
+                    d[c] = out.round() as u8;
+                }
+                d[3] = (out_a * 255.).round() as u8;
+            }
+        }
+    }
+
+    /// Make a region fully transparent.
+    fn clear(&mut self, x: u32, y: u32, w: u32, h: u32) {
+        let rows: Vec<_> = self.rows(x, y, w, h).collect();
+
+        for (_, range) in rows {
+            self.pixels[range].fill(0);
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use std::io::Cursor;
+use std::time::Duration;
+
+use image_webp::WebPDecoder;
+
+use super::{AnimatedImage, AnimatedImageError, AnimationFrame};
+use crate::image_data::{ImageData, PixelFormat};
+
+impl From<image_webp::DecodingError> for AnimatedImageError {
+    fn from(err: image_webp::DecodingError) -> Self {
+        Self::WebP(err)
+    }
+}
+
+impl AnimatedImage {
+    /// Decode all frames of a WebP image.
+    ///
+    /// A WebP image that isn't animated gives a single frame.
+    pub fn from_webp(data: &[u8]) -> Result<Self, AnimatedImageError> {
+        let mut decoder = WebPDecoder::new(Cursor::new(data))?;
+        let (width, height) = decoder.dimensions();
+        let format = match decoder.has_alpha() {
+            true => PixelFormat::Rgba8,
+            false => PixelFormat::Rgb8,
+        };
+
+        let mut buf = vec![0; decoder.output_buffer_size().unwrap()];
+        let mut frames = Vec::new();
+
+        if decoder.is_animated() {
+            for _ in 0..decoder.num_frames() {
+                // The decoder composes the frames itself.
+                let delay = decoder.read_frame(&mut buf)?;
+                frames.push(AnimationFrame {
+                    pixels: ImageData::new(format, width, height, &buf).to_rgba(false),
+                    delay: Duration::from_millis(delay as u64),
+                });
+            }
+        } else {
+            decoder.read_image(&mut buf)?;
+            frames.push(AnimationFrame {
+                pixels: ImageData::new(format, width, height, &buf).to_rgba(false),
+                delay: Duration::ZERO,
+            });
+        }
+
+        Self::from_frames(width, height, frames)
+    }
+}
@@ -0,0 +1,222 @@
+use crate::drawer::{Drawer, ImageSource, SubImage};
+use crate::image_data::ImageData;
+use crate::rect::Rect;
+
+/// Space left around each image in an atlas page, so that neighbouring
+/// images don't bleed into each other when sampled.
+const PADDING: u32 = 1;
+
+struct Shelf {
+    y: u32,
+    height: u32,
+    x: u32,
+}
+
+/// Allocates rectangles inside a fixed-size area.
+///
+/// Rectangles are placed left to right on horizontal shelves. Each new
+/// rectangle goes on the shelf that wastes the least height, and a new
+/// shelf is opened below the last one when none fits.
+pub(crate) struct ShelfPacker {
+    width: u32,
+    height: u32,
+    shelves: Vec<Shelf>,
+}
+
+impl ShelfPacker {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            shelves: Vec::new(),
+        }
+    }
+
+    /// Find room for a rectangle, returning its top-left corner.
+    pub fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        if width > self.width || height > self.height {
+            return None;
+        }
+
+        let best = self
+            .shelves
+            .iter_mut()
+            .filter(|shelf| shelf.height >= height && shelf.x + width <= self.width)
+            .min_by_key(|shelf| shelf.height - height);
+
+        if let Some(shelf) = best {
+            let pos = (shelf.x, shelf.y);
+            shelf.x += width;
+            return Some(pos);
+        }
+
+        let y = self
+            .shelves
+            .last()
+            .map_or(0, |shelf| shelf.y + shelf.height);
+        if y + height > self.height {
+            return None;
+        }
+
+        self.shelves.push(Shelf {
+            y,
+            height,
+            x: width,
+        });
+
+        Some((0, y))
+    }
+
+    /// Forget all allocations.
+    pub fn clear(&mut self) {
+        self.shelves.clear();
+    }
+}
+
+struct AtlasPage {
+    image: ImageSource,
+    packer: ShelfPacker,
+}
+
+/// Packs many small images into a few shared textures.
+///
+/// Each image inserted in the atlas is handed out as a [`SubImage`] that can
+/// be drawn like any other image. Images drawn from the same atlas page
+/// share a single texture, which avoids creating and binding one texture
+/// per image.
+///
+/// Pages are created as needed through the drawer. The sub-images are
+/// valid until the atlas is destroyed with [`ImageAtlas::destroy`] or
+/// dropped.
+pub struct ImageAtlas {
+    page_size: u32,
+    pages: Vec<AtlasPage>,
+}
+
+impl ImageAtlas {
+    /// Create an atlas whose pages are `page_size` pixels wide and high.
+    pub fn new(page_size: u32) -> Self {
+        Self {
+            page_size,
+            pages: Vec::new(),
+        }
+    }
+
+    /// Add an image to the atlas.
+    ///
+    /// Returns `None` if the image is too big to fit on a page.
+    pub fn insert(&mut self, drawer: &mut dyn Drawer, data: &ImageData) -> Option<SubImage> {
+        let (width, height) = (data.width, data.height);
+        let (padded_w, padded_h) = (width + 2 * PADDING, height + 2 * PADDING);
+        if padded_w > self.page_size || padded_h > self.page_size {
+            return None;
+        }
+
+        let found = self.pages.iter_mut().enumerate().find_map(|(i, page)| {
+            let pos = page.packer.allocate(padded_w, padded_h)?;
+            Some((i, pos))
+        });
+
+        let (page_index, (x, y)) = match found {
+            Some(found) => found,
+            None => {
+                let mut packer = ShelfPacker::new(self.page_size, self.page_size);
+                let pos = packer.allocate(padded_w, padded_h)?;
+                let pixels = vec![0; self.page_size as usize * self.page_size as usize * 4];
+
+                self.pages.push(AtlasPage {
+                    image: ImageSource::from_memory(
+                        drawer,
+                        self.page_size,
+                        self.page_size,
+                        &pixels,
+                    ),
+                    packer,
+                });
+
+                (self.pages.len() - 1, pos)
+            }
+        };
+
+        let page = &self.pages[page_index].image;
+        let (x, y) = (x + PADDING, y + PADDING);
+        let region = Rect::new(x as f32, y as f32, width as f32, height as f32);
+        page.update(drawer, x, y, data);
+
+        Some(page.sub_image(&region))
+    }
+
+    /// Number of textures currently used by the atlas.
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Remove all images from the atlas, keeping its pages for reuse.
+    ///
+    /// Sub-images previously returned by the atlas must not be drawn anymore.
+    pub fn clear(&mut self) {
+        for page in &mut self.pages {
+            page.packer.clear();
+        }
+    }
+
+    /// Free all pages of the atlas right away, instead of at the end of the
+    /// frame as when dropped.
+    pub fn destroy(self, drawer: &mut dyn Drawer) {
+        for page in self.pages {
+            page.image.destroy(drawer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlap(a: (u32, u32, u32, u32), b: (u32, u32, u32, u32)) -> bool {
+        a.0 < b.0 + b.2 && b.0 < a.0 + a.2 && a.1 < b.1 + b.3 && b.1 < a.1 + a.3
+    }
+
+    #[test]
+    fn packs_rectangles_without_overlap() {
+        let mut packer = ShelfPacker::new(64, 64);
+        let sizes = [(20, 10), (30, 16), (10, 10), (40, 8), (16, 16), (64, 4)];
+
+        let mut placed = Vec::new();
+        for (w, h) in sizes {
+            let (x, y) = packer.allocate(w, h).unwrap();
+            assert!(x + w <= 64 && y + h <= 64);
+            placed.push((x, y, w, h));
+        }
+
+        for (i, &a) in placed.iter().enumerate() {
+            for &b in &placed[i + 1..] {
+                assert!(!overlap(a, b), "{a:?} overlaps {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn prefers_the_shelf_wasting_the_least_height() {
+        let mut packer = ShelfPacker::new(64, 64);
+        assert_eq!(packer.allocate(10, 20), Some((0, 0)));
+        assert_eq!(packer.allocate(10, 10), Some((10, 0)));
+        assert_eq!(packer.allocate(60, 10), Some((0, 20)));
+        assert_eq!(packer.allocate(4, 10), Some((60, 20)));
+        assert_eq!(packer.allocate(4, 16), Some((20, 0)));
+    }
+
+    #[test]
+    fn fails_when_full_or_too_big() {
+        let mut packer = ShelfPacker::new(32, 32);
+        assert_eq!(packer.allocate(33, 1), None);
+        assert_eq!(packer.allocate(1, 33), None);
+
+        assert_eq!(packer.allocate(32, 20), Some((0, 0)));
+        assert_eq!(packer.allocate(32, 12), Some((0, 20)));
+        assert_eq!(packer.allocate(1, 1), None);
+
+        packer.clear();
+        assert_eq!(packer.allocate(32, 32), Some((0, 0)));
+    }
+}
@@ -1,7 +1,14 @@
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
 use glam::Vec2;
 
-use crate::font::Font;
+use crate::font::FontStack;
+use crate::image_data::ImageData;
+use crate::image_fit::{ImageAlign, ImageFit};
+use crate::layout::{InlineObject, LayoutOptions, LayoutSpan, TextLayout};
 use crate::rect::Rect;
+use crate::video::VideoFrame;
 
 pub struct RectBlueprint {
     pub rect: Rect,
@@ -17,34 +24,375 @@ pub struct TextBlueprint<'a> {
     pub text: &'a str,
     pub x: f32,
     pub y: f32,
-    pub font: &'a Font<'static>,
+    pub font: FontStack<'a>,
     pub size: f32,
     pub col: u32,
     pub alpha: f32,
+    pub outline: Option<TextOutline>,
+    pub shadow: Option<TextShadow>,
+    pub layout: LayoutOptions,
 }
 
 impl<'a> TextBlueprint<'a> {
+    /// Lay out the text into lines, as it will be drawn.
+    pub fn layout(&self) -> TextLayout {
+        TextLayout::new(self.font, self.text, self.size, &self.layout)
+    }
+
+    pub fn text_width(&self) -> f32 {
+        self.layout().width
+    }
+
+    pub fn text_height(&self) -> f32 {
+        self.layout().height
+    }
+
+    /// The text as a single span of rich text.
+    pub fn span(&self) -> TextSpan<'a> {
+        TextSpan {
+            text: self.text,
+            style: TextStyle {
+                alpha: self.alpha,
+                outline: self.outline,
+                shadow: self.shadow,
+                ..TextStyle::new(self.font, self.size, self.col)
+            },
+        }
+    }
+}
+
+/// A stroke drawn around the outline of glyphs.
+///
+/// The width is limited to a sixth of the font size.
+#[derive(Debug, Clone, Copy)]
+pub struct TextOutline {
+    pub width: f32,
+    pub col: u32,
+    pub alpha: f32,
+}
+
+/// A shadow drawn behind glyphs and their outline.
+///
+/// The blur radius is limited to a sixth of the font size.
+#[derive(Debug, Clone, Copy)]
+pub struct TextShadow {
+    pub offset: Vec2,
+    pub blur: f32,
+    pub col: u32,
+    pub alpha: f32,
+}
+
+/// An image placed in text like a character, e.g. a custom emoji or an
+/// avatar in a mention.
+#[derive(Debug, Clone, Copy)]
+pub struct InlineImage {
+    pub image: SubImage,
+    /// Size the image is drawn at.
+    pub width: f32,
+    pub height: f32,
+    /// Distance from the top of the image down to the baseline.
+    pub baseline: f32,
+    /// Room taken on the line, usually the width and some spacing.
+    pub advance: f32,
+}
+
+/// Style of a span of rich text.
+///
+/// Text with an outline or a shadow is always drawn from distance fields,
+/// whatever the text render mode of the drawer.
+#[derive(Clone, Copy)]
+pub struct TextStyle<'a> {
+    pub font: FontStack<'a>,
+    pub size: f32,
+    pub col: u32,
+    pub alpha: f32,
+    pub underline: bool,
+    pub strikethrough: bool,
+    /// Color of a highlight drawn behind the span.
+    pub background: Option<u32>,
+    pub outline: Option<TextOutline>,
+    pub shadow: Option<TextShadow>,
+    /// Draw an image instead of the text of the span, which must not be
+    /// empty. The text is still what gets selected and copied.
+    pub image: Option<InlineImage>,
+}
+
+impl<'a> TextStyle<'a> {
+    /// A plain, opaque style.
+    pub fn new(font: FontStack<'a>, size: f32, col: u32) -> Self {
+        Self {
+            font,
+            size,
+            col,
+            alpha: 1.,
+            underline: false,
+            strikethrough: false,
+            background: None,
+            outline: None,
+            shadow: None,
+            image: None,
+        }
+    }
+}
+
+pub struct TextSpan<'a> {
+    pub text: &'a str,
+    pub style: TextStyle<'a>,
+}
+
+/// A paragraph made of spans of text in different styles.
+pub struct RichTextBlueprint<'a> {
+    pub spans: &'a [TextSpan<'a>],
+    pub x: f32,
+    pub y: f32,
+    pub layout: LayoutOptions,
+}
+
+impl<'a> RichTextBlueprint<'a> {
+    /// The text of all spans put together.
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|span| span.text).collect()
+    }
+
+    /// Lay out the spans into lines, as they will be drawn.
+    ///
+    /// The span of each glyph is its index in [`spans`](Self::spans), and
+    /// text ranges refer to [`text`](Self::text).
+    pub fn layout(&self) -> TextLayout {
+        let mut start = 0;
+        let spans: Vec<LayoutSpan> = self
+            .spans
+            .iter()
+            .map(|span| {
+                let layout_span = LayoutSpan {
+                    start,
+                    fonts: span.style.font,
+                    size: span.style.size,
+                    object: span.style.image.map(|image| InlineObject {
+                        advance: image.advance,
+                        ascent: image.baseline,
+                        descent: image.height - image.baseline,
+                    }),
+                };
+                start += span.text.len();
+                layout_span
+            })
+            .collect();
+
+        TextLayout::from_spans(&self.text(), &spans, &self.layout)
+    }
+
     pub fn text_width(&self) -> f32 {
-        self.font.text_width(self.text, self.size)
+        self.layout().width
     }
 
     pub fn text_height(&self) -> f32 {
-        self.font.get_v_advance(rusttype::Scale::uniform(self.size))
+        self.layout().height
+    }
+}
+
+/// An opaque handle to an image created by a [`Drawer`].
+///
+/// Each drawer backend decides what an image id refers to. Image ids are
+/// only meaningful to the drawer that created them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageId(u32);
+
+impl ImageId {
+    /// Create an image id from a backend-specific value.
+    pub fn from_raw(raw: u32) -> Self {
+        Self(raw)
+    }
+
+    /// The backend-specific value of this image id.
+    pub fn raw(self) -> u32 {
+        self.0
+    }
+}
+
+/// Images dropped without being destroyed, for the drawer that created
+/// them to free.
+#[derive(Debug, Clone, Default)]
+pub struct DroppedImages(Arc<Mutex<Vec<ImageId>>>);
+
+impl DroppedImages {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&self, image: ImageId) {
+        self.0.lock().unwrap().push(image);
+    }
+
+    /// Take the images dropped so far.
+    pub fn take(&self) -> Vec<ImageId> {
+        std::mem::take(&mut *self.0.lock().unwrap())
     }
 }
 
-/// An image to be used with the [img](appy::components::Img) component.
+/// An image created by a [`Drawer`], from pixels in any
+/// [`PixelFormat`](crate::image_data::PixelFormat) or from video frames.
 ///
-/// An image source can only be created from memory, e.g. together with
-/// the `include_bytes!` macro. There is currently no way to load an
-/// image from a file or other assets.
+/// An image source owns its image. [`ImageSource::destroy`] frees it right
+/// away, while dropping the image source has the drawer free it at the
+/// end of its frame, see [`Drawer::dropped_images`].
+///
+/// An image source dereferences to a [`SubImage`] covering the whole
+/// image, so it can be passed anywhere a sub-image is expected. Copy the
+/// sub-image to keep a non-owning handle.
 #[derive(Debug)]
 pub struct ImageSource {
-    pub(crate) id: u32,
+    sub_image: SubImage,
+    /// Where the image goes when dropped, if the drawer frees dropped
+    /// images.
+    dropped: Option<DroppedImages>,
+}
+
+impl ImageSource {
+    /// Wrap an image of a given size created by a drawer.
+    ///
+    /// The image is not freed on drop, as the drawer is not known, only by
+    /// [`ImageSource::destroy`].
+    pub fn new(image: ImageId, width: u32, height: u32) -> Self {
+        Self {
+            sub_image: SubImage {
+                image,
+                uv: Rect::new(0., 0., 1., 1.),
+                width,
+                height,
+            },
+            dropped: None,
+        }
+    }
+
+    /// Wrap an image of a given size created by a drawer, to be freed by
+    /// the drawer when dropped.
+    pub fn created_by(drawer: &dyn Drawer, image: ImageId, width: u32, height: u32) -> Self {
+        Self {
+            dropped: drawer.dropped_images(),
+            ..Self::new(image, width, height)
+        }
+    }
+
+    /// Create an image from tightly packed RGBA pixels.
+    pub fn from_memory(
+        drawer: &mut dyn Drawer,
+        width: u32,
+        height: u32,
+        pixels_rgba: &[u8],
+    ) -> Self {
+        Self::from_data(drawer, &ImageData::rgba(width, height, pixels_rgba))
+    }
+
+    /// Create an image from pixels of any supported format.
+    pub fn from_data(drawer: &mut dyn Drawer, data: &ImageData) -> Self {
+        let image = drawer.create_image(data);
+        Self::created_by(drawer, image, data.width, data.height)
+    }
+
+    /// Create an image from a YUV video frame.
+    ///
+    /// Use [`ImageSource::update_video_frame`] to show the next frames.
+    pub fn from_video_frame(drawer: &mut dyn Drawer, frame: &VideoFrame) -> Self {
+        let image = drawer.create_video_image(frame);
+        Self::created_by(drawer, image, frame.width, frame.height)
+    }
+
+    /// Replace the contents of a video image with a new frame.
+    ///
+    /// If the size of the frame changed, the returned image source has the
+    /// new size.
+    pub fn update_video_frame(mut self, drawer: &mut dyn Drawer, frame: &VideoFrame) -> Self {
+        drawer.update_video_image(self.image, frame);
+        Self {
+            dropped: self.dropped.take(),
+            ..Self::new(self.image, frame.width, frame.height)
+        }
+    }
+
+    /// Replace the region of the image starting at `x` and `y` with new pixels.
+    pub fn update(&self, drawer: &mut dyn Drawer, x: u32, y: u32, data: &ImageData) {
+        drawer.update_image(self.image, x, y, data);
+    }
+
+    /// Free the image.
+    pub fn destroy(mut self, drawer: &mut dyn Drawer) {
+        self.dropped = None;
+        drawer.destroy_image(self.image);
+    }
+}
+
+impl Deref for ImageSource {
+    type Target = SubImage;
+
+    fn deref(&self) -> &SubImage {
+        &self.sub_image
+    }
+}
+
+impl Drop for ImageSource {
+    fn drop(&mut self) {
+        if let Some(dropped) = &self.dropped {
+            dropped.push(self.image);
+        }
+    }
+}
+
+/// A rectangular region of an image.
+///
+/// Sub-images are cheap handles that don't own their image. They are
+/// obtained from an [`ImageSource`] or from an
+/// [`ImageAtlas`](crate::atlas::ImageAtlas), and stay valid for as long
+/// as the image or atlas they come from.
+#[derive(Debug, Clone, Copy)]
+pub struct SubImage {
+    image: ImageId,
+    uv: Rect,
     pub width: u32,
     pub height: u32,
 }
 
+impl SubImage {
+    /// The image this sub-image is part of.
+    pub fn image(&self) -> ImageId {
+        self.image
+    }
+
+    /// Region of the image covered by this sub-image, in texture coordinates.
+    pub fn uv(&self) -> Rect {
+        self.uv
+    }
+
+    /// Get a region of this sub-image, given in its own pixels.
+    pub fn sub_image(&self, region: &Rect) -> SubImage {
+        SubImage {
+            image: self.image,
+            uv: self.region_uv(region),
+            width: region.w as u32,
+            height: region.h as u32,
+        }
+    }
+
+    /// Convert a region given in pixels of this sub-image to texture coordinates.
+    ///
+    /// Regions of a sub-image without pixels all map to its corner.
+    pub fn region_uv(&self, region: &Rect) -> Rect {
+        let scale = |uv: f32, size: u32| match size {
+            0 => 0.,
+            size => uv / size as f32,
+        };
+        let sx = scale(self.uv.w, self.width);
+        let sy = scale(self.uv.h, self.height);
+
+        Rect::new(
+            self.uv.x + region.x * sx,
+            self.uv.y + region.y * sy,
+            region.w * sx,
+            region.h * sy,
+        )
+    }
+}
+
 pub trait Drawer {
     fn resize(&mut self, viewport: Vec2, dpi: f32);
 
@@ -52,7 +400,111 @@ pub trait Drawer {
     fn end_frame(&mut self);
 
     fn clear(&mut self);
+
+    /// Create an image from pixels.
+    ///
+    /// Images created from premultiplied RGBA pixels are stored with
+    /// premultiplied alpha, other images with straight alpha.
+    fn create_image(&mut self, data: &ImageData) -> ImageId;
+
+    /// Replace the region of an image starting at `x` and `y` with new pixels.
+    ///
+    /// The pixels are converted to the alpha mode of the image if needed.
+    fn update_image(&mut self, image: ImageId, x: u32, y: u32, data: &ImageData);
+
+    /// Create an image from a YUV video frame.
+    ///
+    /// The frame is converted to RGB when drawn, not when uploaded.
+    fn create_video_image(&mut self, frame: &VideoFrame) -> ImageId;
+
+    /// Replace the contents of a video image with a new frame.
+    ///
+    /// The new frame may have a different size than the previous one.
+    fn update_video_image(&mut self, image: ImageId, frame: &VideoFrame);
+
+    /// Free an image. Its id must not be used anymore.
+    fn destroy_image(&mut self, image: ImageId);
+
+    /// Images of dropped [`ImageSource`]s are pushed here, to be freed at
+    /// the end of each frame. Drawers without one leave dropped images to
+    /// [`ImageSource::destroy`].
+    fn dropped_images(&self) -> Option<DroppedImages> {
+        None
+    }
+
     fn draw_rect(&mut self, spec: &RectBlueprint);
-    fn draw_text(&mut self, spec: &TextBlueprint);
-    fn draw_image(&mut self, rect: &Rect, image: &ImageSource);
+
+    fn draw_text(&mut self, spec: &TextBlueprint) {
+        self.draw_rich_text(&RichTextBlueprint {
+            spans: &[spec.span()],
+            x: spec.x,
+            y: spec.y,
+            layout: spec.layout,
+        });
+    }
+
+    fn draw_rich_text(&mut self, spec: &RichTextBlueprint);
+    fn draw_image(&mut self, rect: &Rect, image: &SubImage);
+
+    /// Draw the part of an image inside `src`, given in image pixels.
+    fn draw_image_region(&mut self, rect: &Rect, src: &Rect, image: &SubImage);
+
+    /// Draw an image fitted into `rect` according to `fit` and `align`.
+    fn draw_image_fit(&mut self, rect: &Rect, image: &SubImage, fit: ImageFit, align: ImageAlign) {
+        let (dst, src) = fit.compute(image.width as f32, image.height as f32, rect, align);
+        self.draw_image_region(&dst, &src, image);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32) -> SubImage {
+        SubImage {
+            image: ImageId::from_raw(0),
+            uv: Rect::new(0.5, 0.25, 0.5, 0.5),
+            width,
+            height,
+        }
+    }
+
+    fn assert_rect(rect: Rect, expected: [f32; 4]) {
+        let actual = [rect.x, rect.y, rect.w, rect.h];
+        for (a, e) in actual.into_iter().zip(expected) {
+            assert!((a - e).abs() < 1e-6, "{actual:?} is not {expected:?}");
+        }
+    }
+
+    #[test]
+    fn queues_dropped_images_for_the_drawer() {
+        let dropped = DroppedImages::new();
+        let source = |id| ImageSource {
+            dropped: Some(dropped.clone()),
+            ..ImageSource::new(ImageId::from_raw(id), 1, 1)
+        };
+
+        drop(source(1));
+        drop(ImageSource::new(ImageId::from_raw(2), 1, 1));
+        let _kept = source(3);
+
+        assert_eq!(dropped.take(), [ImageId::from_raw(1)]);
+        assert!(dropped.take().is_empty());
+    }
+
+    #[test]
+    fn maps_regions_to_texture_coordinates() {
+        let uv = image(100, 50).region_uv(&Rect::new(50., 25., 20., 10.));
+        assert_rect(uv, [0.75, 0.5, 0.1, 0.1]);
+    }
+
+    #[test]
+    fn maps_regions_of_empty_images_to_their_corner() {
+        let uv = image(0, 0).region_uv(&Rect::new(10., 10., 5., 5.));
+        assert_rect(uv, [0.5, 0.25, 0., 0.]);
+
+        let empty = image(100, 50).sub_image(&Rect::new(10., 10., 0., 0.));
+        let uv = empty.region_uv(&Rect::new(0., 0., 1., 1.));
+        assert!([uv.x, uv.y, uv.w, uv.h].iter().all(|c| c.is_finite()));
+    }
 }
@@ -0,0 +1,971 @@
+use ab_glyph_rasterizer::{point, Point, Rasterizer};
+use glam::{vec2, Affine2, Vec2, Vec4};
+use rustybuzz::ttf_parser::colr::{
+    ClipBox, ColorStop, CompositeMode, GradientExtend, Paint, Painter,
+};
+use rustybuzz::ttf_parser::{GlyphId, OutlineBuilder, RasterImageFormat, RgbaColor, Transform};
+use rustybuzz::Face;
+
+use super::Font;
+use crate::image_data::{ImageData, PixelFormat};
+
+/// A glyph rasterized in color.
+pub(crate) struct ColorGlyph {
+    pub width: u32,
+    pub height: u32,
+    /// Offset of the top-left corner from the glyph origin, in pixels.
+    pub offset: (f32, f32),
+    /// Tightly packed RGBA pixels.
+    pub pixels: Vec<u8>,
+}
+
+/// What the color version of a glyph depends on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ColorGlyphKind {
+    /// A bitmap, or a `COLR` glyph with colors of its own only.
+    Fixed,
+    /// A `COLR` glyph with layers in the color of the text.
+    Foreground,
+}
+
+impl Font<'_> {
+    /// Whether the font has color glyphs at all.
+    pub(crate) fn has_color_glyphs(&self) -> bool {
+        self.color
+    }
+
+    /// Tell whether a glyph has a color version, and whether it uses the
+    /// color of the text.
+    pub(crate) fn color_glyph_kind(&self, id: rusttype::GlyphId) -> Option<ColorGlyphKind> {
+        if !self.color {
+            return None;
+        }
+
+        let face = self.face();
+        let id = GlyphId(id.0);
+        if face.is_color_glyph(id) {
+            // Paint the glyph with two foregrounds and see if it changes.
+            let colors = |foreground| {
+                let mut painter = ColorsPainter::default();
+                face.paint_color_glyph(id, 0, foreground, &mut painter)?;
+                Some(painter.colors)
+            };
+            let black = colors(RgbaColor::new(0, 0, 0, 255))?;
+            let white = colors(RgbaColor::new(255, 255, 255, 255))?;
+
+            return Some(if black == white {
+                ColorGlyphKind::Fixed
+            } else {
+                ColorGlyphKind::Foreground
+            });
+        }
+
+        match face.glyph_raster_image(id, u16::MAX)?.format {
+            #[cfg(feature = "emoji")]
+            RasterImageFormat::PNG => Some(ColorGlyphKind::Fixed),
+            RasterImageFormat::BitmapPremulBgra32 => Some(ColorGlyphKind::Fixed),
+            _ => None,
+        }
+    }
+
+    /// Rasterize the color version of a glyph at a size in pixels, using
+    /// the `COLR` table, or the `CBDT` or `sbix` bitmaps.
+    ///
+    /// `foreground` is the color of the text, used by layers of `COLR`
+    /// glyphs that have no color of their own. Returns `None` if the glyph
+    /// has no color version or no pixels.
+    ///
+    /// Bitmaps stored as PNG, like most emoji fonts use, require the
+    /// `emoji` feature.
+    pub(crate) fn color_glyph(
+        &self,
+        id: rusttype::GlyphId,
+        size: f32,
+        foreground: u32,
+    ) -> Option<ColorGlyph> {
+        if !self.color {
+            return None;
+        }
+
+        let face = self.face();
+        let id = GlyphId(id.0);
+        let v_metrics = self.font.v_metrics_unscaled();
+        let factor = size / (v_metrics.ascent - v_metrics.descent);
+
+        if face.is_color_glyph(id) {
+            paint_colr(&face, id, factor, foreground)
+        } else {
+            raster_glyph(&face, id, factor)
+        }
+    }
+}
+
+/// Decode the bitmap of a glyph and scale it to the size of the text.
+fn raster_glyph(face: &Face, id: GlyphId, factor: f32) -> Option<ColorGlyph> {
+    let ppem = (face.units_per_em() as f32 * factor)
+        .round()
+        .clamp(1., u16::MAX as f32);
+    let image = face.glyph_raster_image(id, ppem as u16)?;
+
+    let (width, height, rgba) = match image.format {
+        #[cfg(feature = "emoji")]
+        RasterImageFormat::PNG => decode_png(image.data)?,
+        RasterImageFormat::BitmapPremulBgra32 => {
+            let (width, height) = (image.width as u32, image.height as u32);
+            let bgra = image.data.get(..(width * height * 4) as usize)?;
+            let rgba: Vec<u8> = bgra
+                .chunks_exact(4)
+                .flat_map(|px| [px[2], px[1], px[0], px[3]])
+                .collect();
+
+            let data = ImageData::new(PixelFormat::PremultipliedRgba8, width, height, &rgba);
+            (width, height, data.to_rgba(false))
+        }
+        // Monochrome bitmaps are left to the outlines.
+        _ => return None,
+    };
+
+    let scale = face.units_per_em() as f32 * factor / image.pixels_per_em as f32;
+    let (scaled_width, scaled_height) = (
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+    );
+
+    let pixels: Vec<Vec4> = rgba.chunks_exact(4).map(premultiplied).collect();
+    let pixels = resample(&pixels, width, height, scaled_width, scaled_height);
+
+    Some(ColorGlyph {
+        width: scaled_width,
+        height: scaled_height,
+        offset: (
+            image.x as f32 * scale,
+            -(image.y as f32 + height as f32) * scale,
+        ),
+        pixels: to_straight_rgba(&pixels),
+    })
+}
+
+#[cfg(feature = "emoji")]
+fn decode_png(data: &[u8]) -> Option<(u32, u32, Vec<u8>)> {
+    use png::{ColorType, Decoder, Transformations};
+
+    let mut decoder = Decoder::new(data);
+    decoder.set_transformations(Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info().ok()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let output = reader.next_frame(&mut buf).ok()?;
+
+    let format = match output.color_type {
+        ColorType::Grayscale => PixelFormat::Gray8,
+        ColorType::GrayscaleAlpha => PixelFormat::GrayAlpha8,
+        ColorType::Rgb | ColorType::Indexed => PixelFormat::Rgb8,
+        ColorType::Rgba => PixelFormat::Rgba8,
+    };
+
+    let rgba = ImageData::new(format, output.width, output.height, &buf)
+        .with_stride(output.line_size as u32)
+        .to_rgba(false);
+
+    Some((output.width, output.height, rgba))
+}
+
+/// Scale premultiplied pixels, averaging the area each pixel covers.
+fn resample(pixels: &[Vec4], width: u32, height: u32, to_width: u32, to_height: u32) -> Vec<Vec4> {
+    let (width, height) = (width as usize, height as usize);
+    let (to_width, to_height) = (to_width as usize, to_height as usize);
+
+    let rows: Vec<Vec4> = pixels
+        .chunks_exact(width)
+        .flat_map(|row| resample_line(row, to_width))
+        .collect();
+
+    let mut out = vec![Vec4::ZERO; to_width * to_height];
+    for x in 0..to_width {
+        let column: Vec<Vec4> = (0..height).map(|y| rows[y * to_width + x]).collect();
+        for (y, px) in resample_line(&column, to_height).into_iter().enumerate() {
+            out[y * to_width + x] = px;
+        }
+    }
+
+    out
+}
+
+fn resample_line(line: &[Vec4], len: usize) -> Vec<Vec4> {
+    let step = line.len() as f32 / len as f32;
+
+    (0..len)
+        .map(|i| {
+            let (start, end) = (i as f32 * step, (i + 1) as f32 * step);
+            let mut sum = Vec4::ZERO;
+
+            for (j, &px) in line
+                .iter()
+                .enumerate()
+                .take(end.ceil() as usize)
+                .skip(start as usize)
+            {
+                let overlap = end.min(j as f32 + 1.) - start.max(j as f32);
+                sum += px * overlap;
+            }
+
+            sum / step
+        })
+        .collect()
+}
+
+fn premultiplied(px: &[u8]) -> Vec4 {
+    let a = px[3] as f32 / 255.;
+    Vec4::new(
+        px[0] as f32 / 255. * a,
+        px[1] as f32 / 255. * a,
+        px[2] as f32 / 255. * a,
+        a,
+    )
+}
+
+fn to_straight_rgba(pixels: &[Vec4]) -> Vec<u8> {
+    pixels
+        .iter()
+        .flat_map(|px| {
+            let rgb = match px.w {
+                0. => Vec4::ZERO,
+                a => *px / a,
+            };
+
+            [rgb.x, rgb.y, rgb.z, px.w].map(|c| (c.clamp(0., 1.) * 255.).round() as u8)
+        })
+        .collect()
+}
+
+/// Paint a `COLR` glyph on a canvas covering its bounds.
+fn paint_colr(face: &Face, id: GlyphId, factor: f32, foreground: u32) -> Option<ColorGlyph> {
+    let foreground = RgbaColor::new(
+        (foreground >> 16) as u8,
+        (foreground >> 8) as u8,
+        foreground as u8,
+        255,
+    );
+
+    let mut bounds = BoundsPainter {
+        face,
+        transforms: vec![Affine2::IDENTITY],
+        min: Vec2::INFINITY,
+        max: Vec2::NEG_INFINITY,
+        clip: None,
+    };
+    face.paint_color_glyph(id, 0, foreground, &mut bounds)?;
+
+    let (mut min, mut max) = (bounds.min, bounds.max);
+    if let Some((clip_min, clip_max)) = bounds.clip {
+        (min, max) = (min.max(clip_min), max.min(clip_max));
+    }
+
+    // Font units have the y axis pointing up.
+    let left = (min.x * factor).floor();
+    let top = (-max.y * factor).floor();
+    let width = ((max.x * factor).ceil() - left) as u32;
+    let height = ((-min.y * factor).ceil() - top) as u32;
+    if min.x >= max.x || min.y >= max.y || width == 0 || height == 0 {
+        return None;
+    }
+
+    let to_pixels = Affine2::from_cols_array(&[factor, 0., 0., -factor, -left, -top]);
+    let mut painter = ColrPainter {
+        face,
+        width: width as usize,
+        height: height as usize,
+        transforms: vec![to_pixels],
+        outline: None,
+        clips: vec![],
+        layers: vec![vec![Vec4::ZERO; (width * height) as usize]],
+        modes: vec![],
+    };
+    face.paint_color_glyph(id, 0, foreground, &mut painter)?;
+
+    Some(ColorGlyph {
+        width,
+        height,
+        offset: (left, top),
+        pixels: to_straight_rgba(&painter.layers[0]),
+    })
+}
+
+fn affine(t: Transform) -> Affine2 {
+    Affine2::from_cols_array(&[t.a, t.b, t.c, t.d, t.e, t.f])
+}
+
+fn clip_box_corners(clip: &ClipBox) -> [Vec2; 4] {
+    [
+        vec2(clip.x_min, clip.y_min),
+        vec2(clip.x_max, clip.y_min),
+        vec2(clip.x_max, clip.y_max),
+        vec2(clip.x_min, clip.y_max),
+    ]
+}
+
+/// Finds the extent of a `COLR` glyph, in font units.
+struct BoundsPainter<'f, 'a> {
+    face: &'f Face<'a>,
+    transforms: Vec<Affine2>,
+    min: Vec2,
+    max: Vec2,
+    /// Bounds of the outermost clip box.
+    clip: Option<(Vec2, Vec2)>,
+}
+
+impl BoundsPainter<'_, '_> {
+    fn transformed(&self, corners: [Vec2; 4]) -> (Vec2, Vec2) {
+        let transform = self.transforms.last().unwrap();
+        corners
+            .map(|p| transform.transform_point2(p))
+            .into_iter()
+            .fold((Vec2::INFINITY, Vec2::NEG_INFINITY), |(min, max), p| {
+                (min.min(p), max.max(p))
+            })
+    }
+}
+
+impl<'a> Painter<'a> for BoundsPainter<'_, 'a> {
+    fn outline_glyph(&mut self, glyph_id: GlyphId) {
+        if let Some(bb) = self.face.glyph_bounding_box(glyph_id) {
+            let (x0, y0, x1, y1) = (
+                bb.x_min as f32,
+                bb.y_min as f32,
+                bb.x_max as f32,
+                bb.y_max as f32,
+            );
+            let (min, max) =
+                self.transformed([vec2(x0, y0), vec2(x1, y0), vec2(x1, y1), vec2(x0, y1)]);
+            self.min = self.min.min(min);
+            self.max = self.max.max(max);
+        }
+    }
+
+    fn push_clip_box(&mut self, clipbox: ClipBox) {
+        if self.clip.is_none() {
+            self.clip = Some(self.transformed(clip_box_corners(&clipbox)));
+        }
+    }
+
+    fn push_transform(&mut self, transform: Transform) {
+        let current = *self.transforms.last().unwrap();
+        self.transforms.push(current * affine(transform));
+    }
+
+    fn pop_transform(&mut self) {
+        self.transforms.pop();
+    }
+
+    fn paint(&mut self, _: Paint<'a>) {}
+    fn push_clip(&mut self) {}
+    fn pop_clip(&mut self) {}
+    fn push_layer(&mut self, _: CompositeMode) {}
+    fn pop_layer(&mut self) {}
+}
+
+/// Collects the colors a `COLR` glyph is painted with.
+#[derive(Default)]
+struct ColorsPainter {
+    colors: Vec<[u8; 4]>,
+}
+
+impl<'a> Painter<'a> for ColorsPainter {
+    fn paint(&mut self, paint: Paint<'a>) {
+        let color = |c: RgbaColor| [c.red, c.green, c.blue, c.alpha];
+        match paint {
+            Paint::Solid(c) => self.colors.push(color(c)),
+            Paint::LinearGradient(g) => self.colors.extend(g.stops(0, &[]).map(|s| color(s.color))),
+            Paint::RadialGradient(g) => self.colors.extend(g.stops(0, &[]).map(|s| color(s.color))),
+            Paint::SweepGradient(g) => self.colors.extend(g.stops(0, &[]).map(|s| color(s.color))),
+        }
+    }
+
+    fn outline_glyph(&mut self, _: GlyphId) {}
+    fn push_clip(&mut self) {}
+    fn push_clip_box(&mut self, _: ClipBox) {}
+    fn pop_clip(&mut self) {}
+    fn push_layer(&mut self, _: CompositeMode) {}
+    fn pop_layer(&mut self) {}
+    fn push_transform(&mut self, _: Transform) {}
+    fn pop_transform(&mut self) {}
+}
+
+/// Paints a `COLR` glyph with premultiplied colors.
+struct ColrPainter<'f, 'a> {
+    face: &'f Face<'a>,
+    width: usize,
+    height: usize,
+    /// Transforms from the current paint space to pixels.
+    transforms: Vec<Affine2>,
+    /// Coverage of the last outlined glyph, until it is filled or used as
+    /// a clip.
+    outline: Option<Vec<f32>>,
+    /// Coverage of the current clip, each one intersected with the ones
+    /// below it.
+    clips: Vec<Vec<f32>>,
+    /// Layers, the first one being the result.
+    layers: Vec<Vec<Vec4>>,
+    /// Composite modes of the layers pushed over the first one.
+    modes: Vec<CompositeMode>,
+}
+
+impl ColrPainter<'_, '_> {
+    fn transform(&self) -> Affine2 {
+        *self.transforms.last().unwrap()
+    }
+
+    /// Coverage of a path drawn by `draw` in the current paint space.
+    fn rasterize(&self, draw: impl FnOnce(&mut PathRasterizer)) -> Vec<f32> {
+        let mut path = PathRasterizer {
+            raster: Rasterizer::new(self.width, self.height),
+            transform: self.transform(),
+            start: point(0., 0.),
+            last: point(0., 0.),
+        };
+        draw(&mut path);
+
+        let mut coverage = vec![0.; self.width * self.height];
+        path.raster
+            .for_each_pixel(|i, c| coverage[i] = c.clamp(0., 1.));
+        coverage
+    }
+
+    fn push_clip_mask(&mut self, mut mask: Vec<f32>) {
+        if let Some(clip) = self.clips.last() {
+            mask.iter_mut().zip(clip).for_each(|(m, c)| *m *= c);
+        }
+        self.clips.push(mask);
+    }
+}
+
+impl<'a> Painter<'a> for ColrPainter<'_, 'a> {
+    fn outline_glyph(&mut self, glyph_id: GlyphId) {
+        let face = self.face;
+        self.outline = Some(self.rasterize(|path| {
+            face.outline_glyph(glyph_id, path);
+            path.close();
+        }));
+    }
+
+    fn paint(&mut self, paint: Paint<'a>) {
+        let inverse = self.transform().inverse();
+        let shader = Shader::new(paint);
+
+        // Layers of COLRv0 glyphs fill their outline directly, while
+        // COLRv1 glyphs fill their clip.
+        let outline = self.outline.take();
+        let clip = self.clips.last();
+        let layer = self.layers.last_mut().unwrap();
+
+        for (i, dst) in layer.iter_mut().enumerate() {
+            let coverage = outline.as_ref().map_or(1., |o| o[i]) * clip.map_or(1., |c| c[i]);
+            if coverage <= 0. {
+                continue;
+            }
+
+            let (x, y) = ((i % self.width) as f32 + 0.5, (i / self.width) as f32 + 0.5);
+            let src = shader.color_at(inverse.transform_point2(vec2(x, y))) * coverage;
+            *dst = src + *dst * (1. - src.w);
+        }
+    }
+
+    fn push_clip(&mut self) {
+        let mask = self.outline.take().unwrap_or_default();
+        self.push_clip_mask(mask);
+    }
+
+    fn push_clip_box(&mut self, clipbox: ClipBox) {
+        let mask = self.rasterize(|path| {
+            let [p0, p1, p2, p3] = clip_box_corners(&clipbox);
+            path.move_to(p0.x, p0.y);
+            path.line_to(p1.x, p1.y);
+            path.line_to(p2.x, p2.y);
+            path.line_to(p3.x, p3.y);
+            path.close();
+        });
+        self.push_clip_mask(mask);
+    }
+
+    fn pop_clip(&mut self) {
+        self.clips.pop();
+    }
+
+    fn push_layer(&mut self, mode: CompositeMode) {
+        self.layers.push(vec![Vec4::ZERO; self.width * self.height]);
+        self.modes.push(mode);
+    }
+
+    fn pop_layer(&mut self) {
+        let (Some(src), Some(mode)) = (self.layers.pop(), self.modes.pop()) else {
+            return;
+        };
+        let dst = self.layers.last_mut().unwrap();
+        for (d, s) in dst.iter_mut().zip(src) {
+            *d = composite(mode, s, *d);
+        }
+    }
+
+    fn push_transform(&mut self, transform: Transform) {
+        let current = self.transform();
+        self.transforms.push(current * affine(transform));
+    }
+
+    fn pop_transform(&mut self) {
+        self.transforms.pop();
+    }
+}
+
+/// Composite premultiplied colors. Blend modes other than the Porter-Duff
+/// operators are drawn as `SourceOver`.
+fn composite(mode: CompositeMode, src: Vec4, dst: Vec4) -> Vec4 {
+    let (sa, da) = (src.w, dst.w);
+
+    match mode {
+        CompositeMode::Clear => Vec4::ZERO,
+        CompositeMode::Source => src,
+        CompositeMode::Destination => dst,
+        CompositeMode::DestinationOver => dst + src * (1. - da),
+        CompositeMode::SourceIn => src * da,
+        CompositeMode::DestinationIn => dst * sa,
+        CompositeMode::SourceOut => src * (1. - da),
+        CompositeMode::DestinationOut => dst * (1. - sa),
+        CompositeMode::SourceAtop => src * da + dst * (1. - sa),
+        CompositeMode::DestinationAtop => dst * sa + src * (1. - da),
+        CompositeMode::Xor => src * (1. - da) + dst * (1. - sa),
+        CompositeMode::Plus => (src + dst).min(Vec4::ONE),
+        _ => src + dst * (1. - sa),
+    }
+}
+
+/// Draws an outline with a transform into a rasterizer.
+struct PathRasterizer {
+    raster: Rasterizer,
+    transform: Affine2,
+    start: Point,
+    last: Point,
+}
+
+impl PathRasterizer {
+    fn point(&self, x: f32, y: f32) -> Point {
+        let p = self.transform.transform_point2(vec2(x, y));
+        point(p.x, p.y)
+    }
+}
+
+impl OutlineBuilder for PathRasterizer {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.close();
+        self.start = self.point(x, y);
+        self.last = self.start;
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let p = self.point(x, y);
+        self.raster.draw_line(self.last, p);
+        self.last = p;
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (p1, p) = (self.point(x1, y1), self.point(x, y));
+        self.raster.draw_quad(self.last, p1, p);
+        self.last = p;
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (p1, p2, p) = (self.point(x1, y1), self.point(x2, y2), self.point(x, y));
+        self.raster.draw_cubic(self.last, p1, p2, p);
+        self.last = p;
+    }
+
+    fn close(&mut self) {
+        if self.last != self.start {
+            self.raster.draw_line(self.last, self.start);
+            self.last = self.start;
+        }
+    }
+}
+
+/// The color of a paint at any point of its paint space.
+enum Shader {
+    Solid(Vec4),
+    Linear {
+        p0: Vec2,
+        /// The gradient vector, scaled so that `t` is `1` at its end.
+        dir: Vec2,
+        stops: ColorStops,
+    },
+    Radial {
+        c0: Vec2,
+        r0: f32,
+        c1: Vec2,
+        r1: f32,
+        stops: ColorStops,
+    },
+    Sweep {
+        center: Vec2,
+        /// Angles in degrees, counter-clockwise.
+        start: f32,
+        end: f32,
+        stops: ColorStops,
+    },
+}
+
+impl Shader {
+    fn new(paint: Paint) -> Self {
+        match paint {
+            Paint::Solid(color) => Shader::Solid(to_vec4(color)),
+            Paint::LinearGradient(g) => {
+                let (p0, p1, p2) = (vec2(g.x0, g.y0), vec2(g.x1, g.y1), vec2(g.x2, g.y2));
+
+                // The gradient runs from p0 to p1, along the normal of the
+                // line from p0 to p2.
+                let normal = (p2 - p0).perp();
+                let dir = match normal.length_squared() {
+                    0. => p1 - p0,
+                    n => normal * (p1 - p0).dot(normal) / n,
+                };
+
+                Shader::Linear {
+                    p0,
+                    dir: dir / dir.length_squared().max(f32::EPSILON),
+                    stops: ColorStops::new(g.stops(0, &[]), g.extend),
+                }
+            }
+            Paint::RadialGradient(g) => Shader::Radial {
+                c0: vec2(g.x0, g.y0),
+                r0: g.r0,
+                c1: vec2(g.x1, g.y1),
+                r1: g.r1,
+                stops: ColorStops::new(g.stops(0, &[]), g.extend),
+            },
+            Paint::SweepGradient(g) => Shader::Sweep {
+                center: vec2(g.center_x, g.center_y),
+                start: (g.start_angle + 1.) * 180.,
+                end: (g.end_angle + 1.) * 180.,
+                stops: ColorStops::new(g.stops(0, &[]), g.extend),
+            },
+        }
+    }
+
+    fn color_at(&self, p: Vec2) -> Vec4 {
+        match self {
+            Shader::Solid(color) => *color,
+            Shader::Linear { p0, dir, stops } => stops.color_at((p - *p0).dot(*dir)),
+            Shader::Radial {
+                c0,
+                r0,
+                c1,
+                r1,
+                stops,
+            } => {
+                // Find the largest t such that p is on the circle
+                // interpolated between both circles at t.
+                let (dc, dr, pd) = (*c1 - *c0, r1 - r0, p - *c0);
+                let a = dc.length_squared() - dr * dr;
+                let b = pd.dot(dc) + r0 * dr;
+                let c = pd.length_squared() - r0 * r0;
+
+                let t = if a.abs() < f32::EPSILON {
+                    (b != 0.).then(|| c / (2. * b))
+                } else {
+                    let discriminant = b * b - a * c;
+                    (discriminant >= 0.).then(|| {
+                        let root = discriminant.sqrt();
+                        let (t0, t1) = ((b + root) / a, (b - root) / a);
+                        let t = t0.max(t1);
+                        if r0 + t * dr >= 0. {
+                            t
+                        } else {
+                            t0.min(t1)
+                        }
+                    })
+                };
+
+                match t {
+                    Some(t) if r0 + t * dr >= 0. => stops.color_at(t),
+                    _ => Vec4::ZERO,
+                }
+            }
+            Shader::Sweep {
+                center,
+                start,
+                end,
+                stops,
+            } => {
+                let d = p - *center;
+                let angle = d.y.atan2(d.x).to_degrees().rem_euclid(360.);
+                match end - start {
+                    0. => Vec4::ZERO,
+                    span => stops.color_at((angle - start) / span),
+                }
+            }
+        }
+    }
+}
+
+/// Sorted stops of a gradient, with premultiplied colors.
+struct ColorStops {
+    stops: Vec<(f32, Vec4)>,
+    extend: GradientExtend,
+}
+
+impl ColorStops {
+    fn new(stops: impl Iterator<Item = ColorStop>, extend: GradientExtend) -> Self {
+        let mut stops: Vec<(f32, Vec4)> = stops
+            .map(|stop| (stop.stop_offset, to_vec4(stop.color)))
+            .collect();
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        Self { stops, extend }
+    }
+
+    fn color_at(&self, t: f32) -> Vec4 {
+        let (Some(first), Some(last)) = (self.stops.first(), self.stops.last()) else {
+            return Vec4::ZERO;
+        };
+
+        // Extend the gradient beyond the range of its stops.
+        let span = last.0 - first.0;
+        let t = if span > 0. {
+            let u = (t - first.0) / span;
+            let u = match self.extend {
+                GradientExtend::Pad => u,
+                GradientExtend::Repeat => u.rem_euclid(1.),
+                GradientExtend::Reflect => 1. - ((u.rem_euclid(2.)) - 1.).abs(),
+            };
+            first.0 + u * span
+        } else {
+            t
+        };
+
+        if t <= first.0 {
+            return first.1;
+        }
+
+        for pair in self.stops.windows(2) {
+            let ((t0, c0), (t1, c1)) = (pair[0], pair[1]);
+            if t <= t1 {
+                let f = if t1 > t0 { (t - t0) / (t1 - t0) } else { 1. };
+                return c0.lerp(c1, f);
+            }
+        }
+
+        last.1
+    }
+}
+
+fn to_vec4(color: RgbaColor) -> Vec4 {
+    let a = color.alpha as f32 / 255.;
+    Vec4::new(
+        color.red as f32 / 255. * a,
+        color.green as f32 / 255. * a,
+        color.blue as f32 / 255. * a,
+        a,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Vec4 = Vec4::new(0., 0., 0., 1.);
+    const WHITE: Vec4 = Vec4::ONE;
+
+    fn gray(level: f32) -> Vec4 {
+        Vec4::new(level, level, level, 1.)
+    }
+
+    fn assert_color(color: Vec4, expected: Vec4) {
+        assert!(
+            color.abs_diff_eq(expected, 1e-4),
+            "{color} is not {expected}"
+        );
+    }
+
+    /// Black to white between `from` and `to`.
+    fn stops(from: f32, to: f32, extend: GradientExtend) -> ColorStops {
+        ColorStops {
+            stops: vec![(from, BLACK), (to, WHITE)],
+            extend,
+        }
+    }
+
+    #[test]
+    fn interpolates_between_stops() {
+        let stops = ColorStops {
+            stops: vec![(0., BLACK), (0.5, Vec4::new(1., 0., 0., 1.)), (1., WHITE)],
+            extend: GradientExtend::Pad,
+        };
+
+        assert_color(stops.color_at(0.25), Vec4::new(0.5, 0., 0., 1.));
+        assert_color(stops.color_at(0.5), Vec4::new(1., 0., 0., 1.));
+        assert_color(stops.color_at(0.75), Vec4::new(1., 0.5, 0.5, 1.));
+    }
+
+    #[test]
+    fn pads_beyond_the_stops() {
+        let stops = stops(0.25, 0.75, GradientExtend::Pad);
+
+        assert_color(stops.color_at(-1.), BLACK);
+        assert_color(stops.color_at(0.5), gray(0.5));
+        assert_color(stops.color_at(2.), WHITE);
+    }
+
+    #[test]
+    fn repeats_beyond_the_stops() {
+        let stops = stops(0.25, 0.75, GradientExtend::Repeat);
+
+        assert_color(stops.color_at(0.125), gray(0.75));
+        assert_color(stops.color_at(0.875), gray(0.25));
+        assert_color(stops.color_at(1.5), gray(0.5));
+    }
+
+    #[test]
+    fn reflects_beyond_the_stops() {
+        let stops = stops(0.25, 0.75, GradientExtend::Reflect);
+
+        assert_color(stops.color_at(0.125), gray(0.25));
+        assert_color(stops.color_at(0.875), gray(0.75));
+        assert_color(stops.color_at(1.5), gray(0.5));
+    }
+
+    #[test]
+    fn has_no_color_without_stops() {
+        let stops = ColorStops {
+            stops: vec![],
+            extend: GradientExtend::Pad,
+        };
+
+        assert_color(stops.color_at(0.5), Vec4::ZERO);
+    }
+
+    #[test]
+    fn shades_concentric_radial_gradients() {
+        let shader = Shader::Radial {
+            c0: Vec2::ZERO,
+            r0: 0.,
+            c1: Vec2::ZERO,
+            r1: 10.,
+            stops: stops(0., 1., GradientExtend::Pad),
+        };
+
+        assert_color(shader.color_at(Vec2::ZERO), BLACK);
+        assert_color(shader.color_at(vec2(0., 5.)), gray(0.5));
+        assert_color(shader.color_at(vec2(-20., 0.)), WHITE);
+    }
+
+    #[test]
+    fn shades_radial_gradients_between_circles() {
+        let shader = Shader::Radial {
+            c0: Vec2::ZERO,
+            r0: 2.,
+            c1: vec2(10., 0.),
+            r1: 2.,
+            stops: stops(0., 1., GradientExtend::Pad),
+        };
+
+        // The last circle through the point wins.
+        assert_color(shader.color_at(vec2(5., 0.)), gray(0.7));
+        // No circle goes through the point.
+        assert_color(shader.color_at(vec2(5., 5.)), Vec4::ZERO);
+    }
+
+    #[test]
+    fn shades_sweep_gradients_counter_clockwise() {
+        let shader = Shader::Sweep {
+            center: Vec2::ZERO,
+            start: 0.,
+            end: 360.,
+            stops: stops(0., 1., GradientExtend::Pad),
+        };
+
+        assert_color(shader.color_at(vec2(1., 0.)), BLACK);
+        assert_color(shader.color_at(vec2(0., 1.)), gray(0.25));
+        assert_color(shader.color_at(vec2(-1., 0.)), gray(0.5));
+        assert_color(shader.color_at(vec2(0., -1.)), gray(0.75));
+    }
+
+    #[test]
+    fn shades_partial_sweep_gradients() {
+        let shader = Shader::Sweep {
+            center: vec2(1., 1.),
+            start: 90.,
+            end: 180.,
+            stops: stops(0., 1., GradientExtend::Pad),
+        };
+
+        assert_color(shader.color_at(vec2(0., 2.)), gray(0.5));
+        assert_color(shader.color_at(vec2(2., 1.)), BLACK);
+        assert_color(shader.color_at(vec2(1., 0.)), WHITE);
+    }
+
+    #[test]
+    fn composites_porter_duff_operators() {
+        let src = Vec4::new(0.5, 0., 0., 0.5);
+        let dst = Vec4::new(0., 0., 1., 1.);
+
+        assert_color(composite(CompositeMode::Clear, src, dst), Vec4::ZERO);
+        assert_color(composite(CompositeMode::Source, src, dst), src);
+        assert_color(composite(CompositeMode::Destination, src, dst), dst);
+        assert_color(
+            composite(CompositeMode::SourceOver, src, dst),
+            Vec4::new(0.5, 0., 0.5, 1.),
+        );
+        assert_color(composite(CompositeMode::DestinationOver, src, dst), dst);
+        assert_color(composite(CompositeMode::SourceIn, src, dst), src);
+        assert_color(composite(CompositeMode::DestinationIn, src, dst), dst * 0.5);
+        assert_color(composite(CompositeMode::SourceOut, src, dst), Vec4::ZERO);
+        assert_color(
+            composite(CompositeMode::DestinationOut, src, dst),
+            dst * 0.5,
+        );
+        assert_color(
+            composite(CompositeMode::SourceAtop, src, dst),
+            Vec4::new(0.5, 0., 0.5, 1.),
+        );
+        assert_color(composite(CompositeMode::Xor, src, dst), dst * 0.5);
+        assert_color(
+            composite(CompositeMode::Plus, src, dst),
+            Vec4::new(0.5, 0., 1., 1.),
+        );
+    }
+
+    #[test]
+    fn composites_blend_modes_as_source_over() {
+        let src = Vec4::new(0.5, 0., 0., 0.5);
+        let dst = Vec4::new(0., 0., 1., 1.);
+
+        assert_color(
+            composite(CompositeMode::Multiply, src, dst),
+            composite(CompositeMode::SourceOver, src, dst),
+        );
+    }
+
+    #[test]
+    fn resamples_by_averaging_covered_pixels() {
+        let pixels = [0., 1., 2., 3.].map(Vec4::splat);
+
+        let half = resample(&pixels, 4, 1, 2, 1);
+        assert_color(half[0], Vec4::splat(0.5));
+        assert_color(half[1], Vec4::splat(2.5));
+
+        let square = resample(&pixels, 2, 2, 1, 1);
+        assert_color(square[0], Vec4::splat(1.5));
+
+        let two_thirds = resample(&pixels[..3], 3, 1, 2, 1);
+        assert_color(two_thirds[0], Vec4::splat(1. / 3.));
+        assert_color(two_thirds[1], Vec4::splat(5. / 3.));
+    }
+
+    #[test]
+    fn resamples_up_by_repeating_pixels() {
+        let pixels = [Vec4::new(0.2, 0.4, 0.6, 1.)];
+        let scaled = resample(&pixels, 1, 1, 3, 2);
+
+        assert_eq!(scaled.len(), 6);
+        for px in scaled {
+            assert_color(px, pixels[0]);
+        }
+    }
+}
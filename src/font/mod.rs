@@ -0,0 +1,463 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use rusttype::{GlyphId, PositionedGlyph, Scale};
+use rustybuzz::ttf_parser::{self, LineMetrics};
+use rustybuzz::{Direction, Face, UnicodeBuffer};
+use unicode_bidi::BidiInfo;
+
+pub use self::registry::{
+    system_font_dirs, FontId, FontProperties, FontQuery, FontRegistry, FontStretch, FontStyle,
+    FontWeight,
+};
+
+pub(crate) use self::color::ColorGlyphKind;
+
+mod color;
+mod registry;
+
+/// A glyph placed by the shaper, in pixels.
+pub(crate) struct ShapedGlyph {
+    pub id: GlyphId,
+    /// Byte index in the shaped text of the first character of the glyph.
+    pub cluster: usize,
+    pub advance: f32,
+    /// Offset of the glyph from its pen position, the y axis pointing down.
+    pub offset: (f32, f32),
+}
+
+#[derive(Debug)]
+pub enum FontError {
+    Io(PathBuf, std::io::Error),
+    /// The data is not a font in a supported format.
+    Invalid,
+    /// A face was asked for past the end of a font collection.
+    FaceIndex {
+        index: u32,
+        count: u32,
+    },
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, err) => write!(f, "Could not read font {}: {err}", path.display()),
+            Self::Invalid => write!(f, "Could not parse font"),
+            Self::FaceIndex { index, count } => {
+                write!(f, "Could not find face {index} in a collection of {count}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FontError {}
+
+impl Clone for FontError {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Io(path, err) => Self::Io(
+                path.clone(),
+                std::io::Error::new(err.kind(), err.to_string()),
+            ),
+            Self::Invalid => Self::Invalid,
+            Self::FaceIndex { index, count } => Self::FaceIndex {
+                index: *index,
+                count: *count,
+            },
+        }
+    }
+}
+
+/// Source of the ids telling fonts apart in glyph caches.
+static NEXT_CACHE_ID: AtomicUsize = AtomicUsize::new(0);
+
+fn next_cache_id() -> usize {
+    NEXT_CACHE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The bytes of a font file.
+#[derive(Clone)]
+enum FontData<'a> {
+    Borrowed(&'a [u8]),
+    /// Shared by the faces of a collection and by clones.
+    Shared(Arc<[u8]>),
+}
+
+/// Represents a font.
+///
+/// A font either borrows its data, e.g. from `include_bytes!`, or shares
+/// it when loaded from a file or a buffer. Cloning a font is cheap and
+/// shares its data.
+#[derive(Clone)]
+pub struct Font<'a> {
+    font: rusttype::Font<'a>,
+    /// Read by the shaper and the color glyph renderer.
+    data: FontData<'a>,
+    /// Index of the face in a font collection.
+    index: u32,
+    /// Unique to each loaded font and shared by its clones.
+    cache_id: usize,
+    /// Whether the font has `COLR`, `CBDT` or `sbix` tables.
+    color: bool,
+}
+
+impl<'a> Font<'a> {
+    /// Use the first face of borrowed font data.
+    ///
+    /// Panics if the data is not a valid font, see [`Font::try_from_data`].
+    pub fn from_data(ttf_data: &'a [u8]) -> Self {
+        Self::try_from_data(ttf_data, 0).unwrap()
+    }
+
+    /// Use a face of borrowed font data, `index` being `0` unless the data
+    /// is a collection like a `.ttc` file.
+    pub fn try_from_data(data: &'a [u8], index: u32) -> Result<Self, FontError> {
+        let color = has_color_tables(&check_face(data, index)?);
+
+        Ok(Self {
+            font: rusttype::Font::try_from_bytes_and_index(data, index)
+                .ok_or(FontError::Invalid)?,
+            data: FontData::Borrowed(data),
+            index,
+            cache_id: next_cache_id(),
+            color,
+        })
+    }
+
+    /// Id of the font in glyph caches, telling apart fonts used together.
+    pub(crate) fn cache_id(&self) -> usize {
+        self.cache_id
+    }
+
+    pub(crate) fn rusttype(&self) -> &rusttype::Font<'_> {
+        &self.font
+    }
+
+    fn data(&self) -> &[u8] {
+        match &self.data {
+            FontData::Borrowed(data) => data,
+            FontData::Shared(data) => data,
+        }
+    }
+
+    pub(crate) fn face(&self) -> Face<'_> {
+        Face::from_slice(self.data(), self.index).unwrap()
+    }
+
+    /// Shape a run of text with a single direction into glyphs, kerning
+    /// and ligatures included.
+    ///
+    /// Glyphs are returned in logical order, even for right-to-left text.
+    pub(crate) fn shape(&self, text: &str, size: f32, rtl: bool) -> Vec<ShapedGlyph> {
+        // The face only borrows the font data and is cheap to create.
+        let face = self.face();
+        let v_metrics = self.font.v_metrics_unscaled();
+        let factor = size / (v_metrics.ascent - v_metrics.descent);
+
+        let mut buffer = UnicodeBuffer::new();
+        buffer.push_str(text);
+        buffer.set_direction(match rtl {
+            true => Direction::RightToLeft,
+            false => Direction::LeftToRight,
+        });
+        buffer.guess_segment_properties();
+
+        let output = rustybuzz::shape(&face, &[], buffer);
+        let mut glyphs: Vec<ShapedGlyph> = output
+            .glyph_infos()
+            .iter()
+            .zip(output.glyph_positions())
+            .map(|(info, pos)| ShapedGlyph {
+                id: GlyphId(info.glyph_id as u16),
+                cluster: info.cluster as usize,
+                advance: pos.x_advance as f32 * factor,
+                offset: (pos.x_offset as f32 * factor, -pos.y_offset as f32 * factor),
+            })
+            .collect();
+
+        if rtl {
+            glyphs.reverse();
+        }
+
+        glyphs
+    }
+
+    /// Whether the font has a glyph for a character.
+    pub fn has_glyph(&self, c: char) -> bool {
+        self.font.glyph(c).id().0 != 0
+    }
+
+    pub fn get_v_advance(&self, scale: Scale) -> f32 {
+        let v_metrics = self.font.v_metrics(scale);
+        v_metrics.ascent - v_metrics.descent + v_metrics.line_gap
+    }
+
+    /// Shape a text into glyphs in visual order, running the bidirectional
+    /// algorithm on it.
+    fn shape_visual(&self, text: &str, size: f32) -> Vec<ShapedGlyph> {
+        let bidi = BidiInfo::new(text, None);
+        let mut glyphs = Vec::with_capacity(text.len());
+
+        for paragraph in &bidi.paragraphs {
+            let (levels, runs) = bidi.visual_runs(paragraph, paragraph.range.clone());
+
+            for run in runs {
+                let rtl = levels[run.start].is_rtl();
+                let mut run_glyphs = self.shape(&text[run.clone()], size, rtl);
+                if rtl {
+                    run_glyphs.reverse();
+                }
+
+                glyphs.extend(run_glyphs.into_iter().map(|glyph| ShapedGlyph {
+                    cluster: run.start + glyph.cluster,
+                    ..glyph
+                }));
+            }
+        }
+
+        glyphs
+    }
+
+    /// Get width in pixels of a string of rendered text, as shaped.
+    pub fn text_width(&self, text: &str, size: f32) -> f32 {
+        self.shape_visual(text, size)
+            .iter()
+            .map(|g| g.advance)
+            .sum()
+    }
+
+    /// Shape a string into glyphs placed on a single line, in visual order.
+    pub fn create_glyphs(&self, s: &str, x: f32, y: f32, size: f32) -> Vec<PositionedGlyph<'_>> {
+        let scale = Scale::uniform(size);
+        let mut pen = x;
+
+        self.shape_visual(s, size)
+            .into_iter()
+            .map(|glyph| {
+                let pos = rusttype::point(pen + glyph.offset.0, y + glyph.offset.1);
+                pen += glyph.advance;
+                self.rusttype()
+                    .glyph(glyph.id)
+                    .scaled(scale)
+                    .positioned(pos)
+            })
+            .collect()
+    }
+
+    pub fn baseline(&self, size: f32) -> f32 {
+        let scale = Scale::uniform(size);
+        let v_metrics = self.font.v_metrics(scale);
+        size + v_metrics.descent
+    }
+
+    /// Offset of the underline below the baseline and its thickness.
+    pub fn underline_metrics(&self, size: f32) -> (f32, f32) {
+        let face = self.face();
+        self.line_metrics(size, face.underline_metrics(), -0.1)
+    }
+
+    /// Offset of the strikethrough line below the baseline and its thickness.
+    pub fn strikeout_metrics(&self, size: f32) -> (f32, f32) {
+        let face = self.face();
+        self.line_metrics(size, face.strikeout_metrics(), 0.3)
+    }
+
+    /// Scale line metrics to pixels. Fonts that lack them get a line at
+    /// `position` times the size above the baseline.
+    fn line_metrics(&self, size: f32, metrics: Option<LineMetrics>, position: f32) -> (f32, f32) {
+        let v_metrics = self.font.v_metrics_unscaled();
+        let factor = size / (v_metrics.ascent - v_metrics.descent);
+
+        match metrics {
+            Some(metrics) if metrics.thickness > 0 => (
+                -metrics.position as f32 * factor,
+                metrics.thickness as f32 * factor,
+            ),
+            _ => (-position * size, size / 14.0),
+        }
+    }
+}
+
+impl Font<'static> {
+    /// Use a face of font data owned by the font.
+    pub fn from_vec(data: Vec<u8>, index: u32) -> Result<Self, FontError> {
+        Self::from_shared(data, index)
+    }
+
+    /// Use a face of font data shared with other fonts, e.g. the other
+    /// faces of a collection.
+    ///
+    /// The shaper reads the shared data, while the glyph rasterizer keeps
+    /// a copy of its own, as it can't share data it doesn't borrow.
+    pub fn from_shared(data: impl Into<Arc<[u8]>>, index: u32) -> Result<Self, FontError> {
+        let data: Arc<[u8]> = data.into();
+        let color = has_color_tables(&check_face(&data, index)?);
+
+        Ok(Self {
+            font: rusttype::Font::try_from_vec_and_index(data.to_vec(), index)
+                .ok_or(FontError::Invalid)?,
+            data: FontData::Shared(data),
+            index,
+            cache_id: next_cache_id(),
+            color,
+        })
+    }
+
+    /// Load a face of a font file.
+    pub fn from_path(path: impl AsRef<Path>, index: u32) -> Result<Self, FontError> {
+        Self::from_shared(read_file(path.as_ref())?, index)
+    }
+}
+
+/// Read a font file whole.
+pub(crate) fn read_file(path: &Path) -> Result<Arc<[u8]>, FontError> {
+    std::fs::read(path)
+        .map(Arc::from)
+        .map_err(|err| FontError::Io(path.to_owned(), err))
+}
+
+/// Number of faces in font data, `1` unless it is a collection.
+pub fn face_count(data: &[u8]) -> u32 {
+    ttf_parser::fonts_in_collection(data).unwrap_or(1)
+}
+
+fn check_face(data: &[u8], index: u32) -> Result<Face<'_>, FontError> {
+    let count = face_count(data);
+    if index >= count {
+        return Err(FontError::FaceIndex { index, count });
+    }
+
+    Face::from_slice(data, index).ok_or(FontError::Invalid)
+}
+
+fn has_color_tables(face: &Face) -> bool {
+    let tables = face.tables();
+    tables.colr.is_some() || tables.cbdt.is_some() || tables.sbix.is_some()
+}
+
+/// A font followed by fallback fonts, used for the characters it lacks.
+///
+/// Each character is drawn with the first font of the stack that has a
+/// glyph for it. Characters that no font has are drawn with the primary
+/// font.
+///
+/// The fonts are either borrowed directly or registered in a
+/// [`FontRegistry`]. Registered fonts that fail to load are skipped.
+#[derive(Clone, Copy)]
+pub struct FontStack<'a> {
+    fonts: StackFonts<'a>,
+}
+
+#[derive(Clone, Copy)]
+enum StackFonts<'a> {
+    Borrowed {
+        primary: &'a Font<'static>,
+        fallbacks: &'a [&'a Font<'static>],
+    },
+    Registered {
+        registry: &'a FontRegistry,
+        primary: FontId,
+        fallbacks: &'a [FontId],
+    },
+}
+
+impl<'a> FontStack<'a> {
+    pub fn new(primary: &'a Font<'static>, fallbacks: &'a [&'a Font<'static>]) -> Self {
+        Self {
+            fonts: StackFonts::Borrowed { primary, fallbacks },
+        }
+    }
+
+    /// A stack of fonts registered in a registry.
+    pub fn from_registry(
+        registry: &'a FontRegistry,
+        primary: FontId,
+        fallbacks: &'a [FontId],
+    ) -> Self {
+        Self {
+            fonts: StackFonts::Registered {
+                registry,
+                primary,
+                fallbacks,
+            },
+        }
+    }
+
+    /// The font giving the line metrics of the text.
+    pub fn primary(&self) -> &'a Font<'static> {
+        self.get(0)
+    }
+
+    /// Get a font by its index in the stack, the primary font being `0`.
+    ///
+    /// A font that fails to load is replaced with the first font of the
+    /// stack that loads. Panics if none does.
+    pub fn get(&self, index: usize) -> &'a Font<'static> {
+        self.try_get(index)
+            .or_else(|| self.iter().next())
+            .expect("no font of the stack could be loaded")
+    }
+
+    /// Get a font by its index in the stack, unless it fails to load.
+    fn try_get(&self, index: usize) -> Option<&'a Font<'static>> {
+        match self.fonts {
+            StackFonts::Borrowed { primary, .. } if index == 0 => Some(primary),
+            StackFonts::Borrowed { fallbacks, .. } => Some(fallbacks[index - 1]),
+            StackFonts::Registered {
+                registry,
+                primary,
+                fallbacks,
+            } => {
+                let id = match index {
+                    0 => primary,
+                    _ => fallbacks[index - 1],
+                };
+                registry.try_get(id).ok()
+            }
+        }
+    }
+
+    /// Number of fonts in the stack, the primary font included.
+    fn len(&self) -> usize {
+        match self.fonts {
+            StackFonts::Borrowed { fallbacks, .. } => fallbacks.len() + 1,
+            StackFonts::Registered { fallbacks, .. } => fallbacks.len() + 1,
+        }
+    }
+
+    /// Iterate over the fonts of the stack that load, starting with the
+    /// primary font.
+    pub fn iter(&self) -> impl Iterator<Item = &'a Font<'static>> {
+        let stack = *self;
+        (0..self.len()).filter_map(move |index| stack.try_get(index))
+    }
+
+    /// Index of the first font having glyphs for all the characters of a
+    /// string, ignoring joiners and variation selectors.
+    pub fn font_for(&self, text: &str) -> Option<usize> {
+        let chars = text.chars().filter(|&c| !is_ignorable(c));
+        (0..self.len()).find(|&index| {
+            self.try_get(index)
+                .is_some_and(|font| chars.clone().all(|c| font.has_glyph(c)))
+        })
+    }
+}
+
+/// Characters that are usually not mapped to a glyph of their own.
+fn is_ignorable(c: char) -> bool {
+    matches!(c, '\u{200c}' | '\u{200d}' | '\u{fe00}'..='\u{fe0f}' | '\u{e0100}'..='\u{e01ef}')
+}
+
+impl<'a> From<&'a Font<'static>> for FontStack<'a> {
+    fn from(font: &'a Font<'static>) -> Self {
+        Self::new(font, &[])
+    }
+}
+
+impl<'a> From<(&'a FontRegistry, FontId)> for FontStack<'a> {
+    fn from((registry, id): (&'a FontRegistry, FontId)) -> Self {
+        Self::from_registry(registry, id, &[])
+    }
+}
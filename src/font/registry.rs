@@ -0,0 +1,699 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use rustybuzz::ttf_parser::{self, name_id};
+
+use super::{face_count, read_file, Font, FontError, FontStack};
+
+/// Extensions of the font files picked up when scanning directories.
+const FONT_EXTENSIONS: [&str; 4] = ["ttf", "otf", "ttc", "otc"];
+
+/// A cheap handle to a font of a [`FontRegistry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FontId(u32);
+
+/// Weight of a font, from `100` (thin) to `900` (black).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FontWeight(pub u16);
+
+impl FontWeight {
+    pub const THIN: Self = Self(100);
+    pub const EXTRA_LIGHT: Self = Self(200);
+    pub const LIGHT: Self = Self(300);
+    pub const NORMAL: Self = Self(400);
+    pub const MEDIUM: Self = Self(500);
+    pub const SEMI_BOLD: Self = Self(600);
+    pub const BOLD: Self = Self(700);
+    pub const EXTRA_BOLD: Self = Self(800);
+    pub const BLACK: Self = Self(900);
+}
+
+impl Default for FontWeight {
+    fn default() -> Self {
+        Self::NORMAL
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FontStyle {
+    #[default]
+    Normal,
+    Italic,
+    Oblique,
+}
+
+/// Width of a font, from the narrowest to the widest.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FontStretch {
+    UltraCondensed,
+    ExtraCondensed,
+    Condensed,
+    SemiCondensed,
+    #[default]
+    Normal,
+    SemiExpanded,
+    Expanded,
+    ExtraExpanded,
+    UltraExpanded,
+}
+
+/// What a font is registered as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FontProperties {
+    pub family: String,
+    pub weight: FontWeight,
+    pub style: FontStyle,
+    pub stretch: FontStretch,
+}
+
+impl FontProperties {
+    /// Read the properties a font declares in its tables.
+    pub fn of(font: &Font) -> Self {
+        Self::of_face(&font.face())
+    }
+
+    fn of_face(face: &ttf_parser::Face) -> Self {
+        let family = [name_id::TYPOGRAPHIC_FAMILY, name_id::FAMILY]
+            .into_iter()
+            .find_map(|id| {
+                face.names()
+                    .into_iter()
+                    .filter(|name| name.name_id == id && name.is_unicode())
+                    .find_map(|name| name.to_string())
+            })
+            .unwrap_or_default();
+
+        let style = match face.style() {
+            ttf_parser::Style::Normal => FontStyle::Normal,
+            ttf_parser::Style::Italic => FontStyle::Italic,
+            ttf_parser::Style::Oblique => FontStyle::Oblique,
+        };
+
+        let stretch = match face.width() {
+            ttf_parser::Width::UltraCondensed => FontStretch::UltraCondensed,
+            ttf_parser::Width::ExtraCondensed => FontStretch::ExtraCondensed,
+            ttf_parser::Width::Condensed => FontStretch::Condensed,
+            ttf_parser::Width::SemiCondensed => FontStretch::SemiCondensed,
+            ttf_parser::Width::Normal => FontStretch::Normal,
+            ttf_parser::Width::SemiExpanded => FontStretch::SemiExpanded,
+            ttf_parser::Width::Expanded => FontStretch::Expanded,
+            ttf_parser::Width::ExtraExpanded => FontStretch::ExtraExpanded,
+            ttf_parser::Width::UltraExpanded => FontStretch::UltraExpanded,
+        };
+
+        Self {
+            family,
+            weight: FontWeight(face.weight().to_number()),
+            style,
+            stretch,
+        }
+    }
+}
+
+/// What to look for in a [`FontRegistry`].
+#[derive(Debug, Clone, Copy)]
+pub struct FontQuery<'a> {
+    pub family: &'a str,
+    pub weight: FontWeight,
+    pub style: FontStyle,
+    pub stretch: FontStretch,
+}
+
+impl<'a> FontQuery<'a> {
+    /// A query for the regular face of a family.
+    pub fn new(family: &'a str) -> Self {
+        Self {
+            family,
+            weight: FontWeight::NORMAL,
+            style: FontStyle::Normal,
+            stretch: FontStretch::Normal,
+        }
+    }
+}
+
+/// A collection of fonts, indexed by family, weight, style and stretch.
+///
+/// Fonts are handed out as [`FontId`] handles, which are valid for the
+/// registry that created them.
+///
+/// Fonts found by scanning directories are only read from disk the first
+/// time they are used. The faces of a collection share a single copy of
+/// its file.
+#[derive(Default)]
+pub struct FontRegistry {
+    fonts: Vec<Entry>,
+}
+
+struct Entry {
+    /// The font once loaded, or why it couldn't be.
+    font: OnceLock<Result<Font<'static>, FontError>>,
+    properties: FontProperties,
+    /// File and face index to load the font from, if not loaded yet.
+    source: Option<(Arc<FontFile>, u32)>,
+}
+
+/// A font file found by scanning a directory, read once for all its faces.
+struct FontFile {
+    path: PathBuf,
+    data: OnceLock<Arc<[u8]>>,
+}
+
+impl FontFile {
+    fn data(&self) -> Result<Arc<[u8]>, FontError> {
+        if let Some(data) = self.data.get() {
+            return Ok(data.clone());
+        }
+
+        let data = read_file(&self.path)?;
+        Ok(self.data.get_or_init(|| data).clone())
+    }
+}
+
+impl FontRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a font with the properties it declares.
+    pub fn add(&mut self, font: Font<'static>) -> FontId {
+        let properties = FontProperties::of(&font);
+        self.add_as(font, properties)
+    }
+
+    /// Add a font with properties of choice, e.g. when its tables are
+    /// missing or wrong.
+    pub fn add_as(&mut self, font: Font<'static>, properties: FontProperties) -> FontId {
+        self.push(Entry {
+            font: OnceLock::from(Ok(font)),
+            properties,
+            source: None,
+        })
+    }
+
+    /// Add all faces of a font file.
+    pub fn add_file(&mut self, path: impl AsRef<Path>) -> Result<Vec<FontId>, FontError> {
+        self.add_shared(read_file(path.as_ref())?)
+    }
+
+    /// Add all faces of font data, sharing it between them.
+    pub fn add_shared(&mut self, data: impl Into<Arc<[u8]>>) -> Result<Vec<FontId>, FontError> {
+        let data: Arc<[u8]> = data.into();
+
+        (0..face_count(&data))
+            .map(|index| Ok(self.add(Font::from_shared(data.clone(), index)?)))
+            .collect()
+    }
+
+    /// Add the faces of all font files in a directory and its
+    /// subdirectories, skipping files that are not valid fonts.
+    pub fn add_dir(&mut self, dir: impl AsRef<Path>) -> Result<Vec<FontId>, FontError> {
+        self.scan_dir(dir.as_ref(), &mut HashSet::new())
+    }
+
+    /// Add the fonts installed on the system, from the directories listed
+    /// by [`system_font_dirs`].
+    pub fn add_system_fonts(&mut self) -> Vec<FontId> {
+        let mut visited = HashSet::new();
+
+        system_font_dirs()
+            .into_iter()
+            .filter(|dir| dir.is_dir())
+            .flat_map(|dir| self.scan_dir(&dir, &mut visited).unwrap_or_default())
+            .collect()
+    }
+
+    /// Add the fonts of a directory unless it was visited before, e.g.
+    /// through a symbolic link looping back to a parent.
+    fn scan_dir(
+        &mut self,
+        dir: &Path,
+        visited: &mut HashSet<PathBuf>,
+    ) -> Result<Vec<FontId>, FontError> {
+        let io_err = |err| FontError::Io(dir.to_owned(), err);
+        if !visited.insert(dir.canonicalize().map_err(io_err)?) {
+            return Ok(vec![]);
+        }
+
+        let mut paths = std::fs::read_dir(dir)
+            .map_err(io_err)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(io_err)?;
+        paths.sort();
+
+        let mut ids = vec![];
+        for path in paths {
+            if path.is_dir() {
+                // Unreadable subdirectories are skipped like invalid fonts.
+                ids.extend(self.scan_dir(&path, visited).unwrap_or_default());
+            } else if is_font_file(&path) {
+                ids.extend(self.scan_file(path).unwrap_or_default());
+            }
+        }
+
+        Ok(ids)
+    }
+
+    /// Register the faces of a font file, reading only the tables that
+    /// tell their properties.
+    fn scan_file(&mut self, path: PathBuf) -> Result<Vec<FontId>, FontError> {
+        let faces = read_face_tables(&path).map_err(|err| match err.kind() {
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => FontError::Invalid,
+            _ => FontError::Io(path.clone(), err),
+        })?;
+        let file = Arc::new(FontFile {
+            path,
+            data: OnceLock::new(),
+        });
+
+        let mut ids = vec![];
+        for (index, tables) in faces.iter().enumerate() {
+            let face = ttf_parser::Face::parse(tables, 0).map_err(|_| FontError::Invalid)?;
+
+            ids.push(self.push(Entry {
+                font: OnceLock::new(),
+                properties: FontProperties::of_face(&face),
+                source: Some((file.clone(), index as u32)),
+            }));
+        }
+
+        Ok(ids)
+    }
+
+    fn push(&mut self, entry: Entry) -> FontId {
+        self.fonts.push(entry);
+        FontId(self.fonts.len() as u32 - 1)
+    }
+
+    /// Get a font, loading it from disk if needed.
+    ///
+    /// Fonts found by scanning a directory fail to load if their file was
+    /// removed or changed since. The error is kept, so the file is not
+    /// read again.
+    pub fn try_get(&self, id: FontId) -> Result<&Font<'static>, FontError> {
+        let entry = &self.fonts[id.0 as usize];
+
+        entry
+            .font
+            .get_or_init(|| {
+                let (file, index) = entry.source.as_ref().unwrap();
+                Font::from_shared(file.data()?, *index)
+            })
+            .as_ref()
+            .map_err(FontError::clone)
+    }
+
+    /// Get a font, loading it from disk if needed.
+    ///
+    /// Panics if the font can't be loaded, see [`FontRegistry::try_get`].
+    pub fn get(&self, id: FontId) -> &Font<'static> {
+        self.try_get(id).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn properties(&self, id: FontId) -> &FontProperties {
+        &self.fonts[id.0 as usize].properties
+    }
+
+    /// Iterate over the fonts of the registry.
+    pub fn ids(&self) -> impl Iterator<Item = FontId> {
+        (0..self.fonts.len() as u32).map(FontId)
+    }
+
+    /// Find the font of a family closest to a query, as CSS matches fonts.
+    ///
+    /// Family names are compared case-insensitively. The stretch is
+    /// matched first, then the style and the weight.
+    pub fn find(&self, query: &FontQuery) -> Option<FontId> {
+        let mut candidates: Vec<(FontId, &FontProperties)> = self
+            .ids()
+            .map(|id| (id, self.properties(id)))
+            .filter(|(_, props)| props.family.eq_ignore_ascii_case(query.family))
+            .collect();
+
+        let stretch = candidates
+            .iter()
+            .map(|(_, props)| props.stretch)
+            .min_by_key(|&stretch| stretch_rank(query.stretch, stretch))?;
+        candidates.retain(|(_, props)| props.stretch == stretch);
+
+        let style = candidates
+            .iter()
+            .map(|(_, props)| props.style)
+            .min_by_key(|&style| style_rank(query.style, style))?;
+        candidates.retain(|(_, props)| props.style == style);
+
+        candidates
+            .into_iter()
+            .min_by_key(|(_, props)| weight_rank(query.weight, props.weight))
+            .map(|(id, _)| id)
+    }
+
+    /// A font stack of registered fonts.
+    pub fn stack<'a>(&'a self, primary: FontId, fallbacks: &'a [FontId]) -> FontStack<'a> {
+        FontStack::from_registry(self, primary, fallbacks)
+    }
+}
+
+/// Order of preference of a stretch: narrower ones first for a normal or
+/// narrower query, wider ones first otherwise, closest first.
+fn stretch_rank(query: FontStretch, stretch: FontStretch) -> (bool, u8) {
+    let (query, stretch) = (query as u8, stretch as u8);
+    let narrow = query <= FontStretch::Normal as u8;
+
+    match stretch.cmp(&query) {
+        std::cmp::Ordering::Equal => (false, 0),
+        std::cmp::Ordering::Less => (!narrow, query - stretch),
+        std::cmp::Ordering::Greater => (narrow, stretch - query),
+    }
+}
+
+fn style_rank(query: FontStyle, style: FontStyle) -> u8 {
+    use FontStyle::*;
+
+    let order = match query {
+        Normal => [Normal, Oblique, Italic],
+        Italic => [Italic, Oblique, Normal],
+        Oblique => [Oblique, Italic, Normal],
+    };
+
+    order.iter().position(|&s| s == style).unwrap() as u8
+}
+
+/// Order of preference of a weight: for a query between `400` and `500`,
+/// heavier weights up to `500` come first, then lighter ones, then heavier
+/// ones. Lighter queries prefer lighter weights, heavier queries prefer
+/// heavier weights.
+fn weight_rank(query: FontWeight, weight: FontWeight) -> (u8, u16) {
+    let (query, weight) = (query.0, weight.0);
+
+    if (400..=500).contains(&query) {
+        match weight {
+            w if (query..=500).contains(&w) => (0, w - query),
+            w if w < query => (1, query - w),
+            w => (2, w - query),
+        }
+    } else if query < 400 {
+        match weight {
+            w if w <= query => (0, query - w),
+            w => (1, w - query),
+        }
+    } else {
+        match weight {
+            w if w >= query => (0, w - query),
+            w => (1, query - w),
+        }
+    }
+}
+
+fn is_font_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| FONT_EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+}
+
+/// Directories where fonts are installed on the current platform, in the
+/// order fontconfig and the platform conventions give them. Some of them
+/// may not exist.
+pub fn system_font_dirs() -> Vec<PathBuf> {
+    let env = |name: &str| std::env::var_os(name).map(PathBuf::from);
+    let home = env("HOME");
+    let mut dirs = vec![];
+
+    if cfg!(target_os = "windows") {
+        dirs.extend(env("WINDIR").map(|dir| dir.join("Fonts")));
+        dirs.extend(env("LOCALAPPDATA").map(|dir| dir.join("Microsoft/Windows/Fonts")));
+    } else if cfg!(target_os = "macos") {
+        dirs.push("/System/Library/Fonts".into());
+        dirs.push("/Library/Fonts".into());
+        dirs.extend(home.map(|home| home.join("Library/Fonts")));
+    } else {
+        let data_home =
+            env("XDG_DATA_HOME").or(home.as_ref().map(|home| home.join(".local/share")));
+        dirs.extend(data_home.map(|dir| dir.join("fonts")));
+        dirs.extend(home.map(|home| home.join(".fonts")));
+
+        let data_dirs = std::env::var("XDG_DATA_DIRS")
+            .ok()
+            .filter(|dirs| !dirs.is_empty())
+            .unwrap_or_else(|| "/usr/local/share:/usr/share".into());
+        dirs.extend(data_dirs.split(':').map(|dir| Path::new(dir).join("fonts")));
+    }
+
+    dirs
+}
+
+/// Tables read when scanning a font file: the ones a face can't be parsed
+/// without, and the ones holding its properties.
+const SCANNED_TABLES: [&[u8; 4]; 5] = [b"head", b"hhea", b"maxp", b"name", b"OS/2"];
+
+/// Read the scanned tables of each face of a font file, each face as a
+/// font of its own, without reading the rest of the file.
+fn read_face_tables(path: &Path) -> io::Result<Vec<Vec<u8>>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    let mut read = |offset: u64, size: u64| -> io::Result<Vec<u8>> {
+        if offset + size > len {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        let mut buf = vec![0; size as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buf)?;
+        Ok(buf)
+    };
+    let u16_at = |buf: &[u8], i: usize| u16::from_be_bytes([buf[i], buf[i + 1]]);
+    let u32_at = |buf: &[u8], i: usize| u32::from_be_bytes(buf[i..i + 4].try_into().unwrap());
+
+    // A collection starts with the offsets of its faces.
+    let header = read(0, 12)?;
+    let offsets: Vec<u64> = if &header[..4] == b"ttcf" {
+        let offsets = read(12, u32_at(&header, 8) as u64 * 4)?;
+        offsets
+            .chunks_exact(4)
+            .map(|o| u32_at(o, 0) as u64)
+            .collect()
+    } else {
+        vec![0]
+    };
+
+    let mut faces = vec![];
+    for offset in offsets {
+        let header = read(offset, 12)?;
+        let records = read(offset + 12, u16_at(&header, 4) as u64 * 16)?;
+        let tables: Vec<(&[u8], u64, u64)> = records
+            .chunks_exact(16)
+            .map(|r| (&r[..4], u32_at(r, 8) as u64, u32_at(r, 12) as u64))
+            .filter(|(tag, ..)| SCANNED_TABLES.iter().any(|scanned| scanned == tag))
+            .collect();
+
+        // Table records followed by the tables, each aligned to 4 bytes.
+        let mut font = header[..4].to_vec();
+        font.extend((tables.len() as u16).to_be_bytes());
+        font.extend([0; 6]);
+        let mut data = vec![];
+        for &(tag, offset, size) in &tables {
+            let table_offset = 12 + tables.len() * 16 + data.len();
+            font.extend(tag);
+            font.extend([0; 4]);
+            font.extend((table_offset as u32).to_be_bytes());
+            font.extend((size as u32).to_be_bytes());
+
+            data.extend(read(offset, size)?);
+            data.resize(data.len().next_multiple_of(4), 0);
+        }
+
+        font.extend(data);
+        faces.push(font);
+    }
+
+    Ok(faces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roboto() -> Font<'static> {
+        Font::from_data(include_bytes!("../../examples/common/Roboto-Regular.ttf"))
+    }
+
+    /// A registry with the same font registered as the given faces.
+    fn registry_of(faces: &[(u16, FontStyle, FontStretch)]) -> FontRegistry {
+        let mut registry = FontRegistry::new();
+        for &(weight, style, stretch) in faces {
+            let properties = FontProperties {
+                family: "Test".to_string(),
+                weight: FontWeight(weight),
+                style,
+                stretch,
+            };
+            registry.add_as(roboto(), properties);
+        }
+        registry
+    }
+
+    fn find(registry: &FontRegistry, weight: u16, style: FontStyle, stretch: FontStretch) -> u32 {
+        let query = FontQuery {
+            weight: FontWeight(weight),
+            style,
+            stretch,
+            ..FontQuery::new("test")
+        };
+        registry.find(&query).unwrap().0
+    }
+
+    #[test]
+    fn reads_the_properties_of_a_font() {
+        let mut registry = FontRegistry::new();
+        let id = registry.add(roboto());
+
+        let properties = registry.properties(id);
+        assert_eq!(properties.family, "Roboto");
+        assert_eq!(properties.weight, FontWeight::NORMAL);
+        assert_eq!(properties.style, FontStyle::Normal);
+        assert_eq!(properties.stretch, FontStretch::Normal);
+    }
+
+    #[test]
+    fn shares_font_data_between_faces() {
+        let data: Arc<[u8]> = Arc::from(roboto().data());
+        let mut registry = FontRegistry::new();
+        let ids = registry.add_shared(data.clone()).unwrap();
+
+        assert_eq!(ids.len(), 1);
+        assert_eq!(registry.get(ids[0]).data().as_ptr(), data.as_ptr());
+    }
+
+    /// A collection with the same font as each of its faces.
+    fn collection_of(font: &[u8], faces: u32) -> Vec<u8> {
+        let header_len = 12 + faces * 4;
+        let mut data = b"ttcf".to_vec();
+        data.extend(0x0001_0000u32.to_be_bytes());
+        data.extend(faces.to_be_bytes());
+        for _ in 0..faces {
+            data.extend(header_len.to_be_bytes());
+        }
+
+        // Table offsets are from the start of the file.
+        let mut font = font.to_vec();
+        let tables = u16::from_be_bytes([font[4], font[5]]) as usize;
+        for record in font[12..12 + tables * 16].chunks_exact_mut(16) {
+            let offset = u32::from_be_bytes(record[8..12].try_into().unwrap()) + header_len;
+            record[8..12].copy_from_slice(&offset.to_be_bytes());
+        }
+
+        data.extend(font);
+        data
+    }
+
+    #[test]
+    fn scans_the_faces_of_collections() {
+        let dir = std::env::temp_dir().join(format!("loki-draw-ttc-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Roboto.ttc"), collection_of(roboto().data(), 2)).unwrap();
+        std::fs::write(dir.join("Broken.ttf"), &roboto().data()[..100]).unwrap();
+
+        let mut registry = FontRegistry::new();
+        let ids = registry.add_dir(&dir).unwrap();
+        let expected = FontProperties::of(&roboto());
+
+        assert_eq!(ids.len(), 2);
+        for id in ids {
+            assert_eq!(registry.properties(id), &expected);
+            assert_eq!(FontProperties::of(registry.get(id)), expected);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scans_directories_once_through_symbolic_links() {
+        let dir = std::env::temp_dir().join(format!("loki-draw-links-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/Roboto.ttf"), roboto().data()).unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("sub/parent")).unwrap();
+
+        let ids = FontRegistry::new().add_dir(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(ids.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn skips_fonts_removed_after_scanning() {
+        let dir = std::env::temp_dir().join(format!("loki-draw-fonts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Roboto.ttf"), roboto().data()).unwrap();
+
+        let mut registry = FontRegistry::new();
+        let scanned = registry.add_dir(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let loaded = registry.add(roboto());
+
+        assert_eq!(scanned.len(), 1);
+        assert!(matches!(
+            registry.try_get(scanned[0]),
+            Err(FontError::Io(..))
+        ));
+        assert!(registry.try_get(loaded).is_ok());
+
+        let fallbacks = [loaded];
+        let stack = registry.stack(scanned[0], &fallbacks);
+        assert_eq!(stack.font_for("a"), Some(1));
+        assert_eq!(stack.iter().count(), 1);
+        assert_eq!(stack.primary().cache_id(), registry.get(loaded).cache_id());
+    }
+
+    #[test]
+    fn finds_families_case_insensitively() {
+        let registry = registry_of(&[(400, FontStyle::Normal, FontStretch::Normal)]);
+        assert!(registry.find(&FontQuery::new("TEST")).is_some());
+        assert!(registry.find(&FontQuery::new("Other")).is_none());
+    }
+
+    #[test]
+    fn ranks_weights_like_css() {
+        use FontStretch::Normal as N;
+        use FontStyle::Normal as S;
+
+        let registry = registry_of(&[(300, S, N), (500, S, N), (700, S, N)]);
+        // Between 400 and 500, up to 500 first.
+        assert_eq!(find(&registry, 400, S, N), 1);
+        // Lighter queries go lighter first.
+        assert_eq!(find(&registry, 350, S, N), 0);
+        assert_eq!(find(&registry, 200, S, N), 0);
+        // Heavier queries go heavier first.
+        assert_eq!(find(&registry, 600, S, N), 2);
+        assert_eq!(find(&registry, 900, S, N), 2);
+
+        let registry = registry_of(&[(300, S, N), (600, S, N)]);
+        // Then lighter ones, then heavier ones.
+        assert_eq!(find(&registry, 450, S, N), 0);
+    }
+
+    #[test]
+    fn matches_stretch_then_style_then_weight() {
+        use FontStretch::{Condensed, Expanded, SemiCondensed, SemiExpanded};
+        use FontStyle::{Italic, Oblique};
+        const N: FontStretch = FontStretch::Normal;
+        const S: FontStyle = FontStyle::Normal;
+
+        let registry = registry_of(&[
+            (400, S, Condensed),
+            (700, Italic, N),
+            (400, Oblique, N),
+            (400, S, Expanded),
+        ]);
+
+        // The normal stretch wins over a matching style.
+        assert_eq!(find(&registry, 400, S, N), 2);
+        assert_eq!(find(&registry, 400, Italic, N), 1);
+        // Narrower stretches are preferred for normal and narrower queries.
+        assert_eq!(find(&registry, 400, S, SemiCondensed), 0);
+        // Wider ones for wider queries.
+        assert_eq!(find(&registry, 400, S, SemiExpanded), 3);
+    }
+}
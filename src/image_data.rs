@@ -0,0 +1,190 @@
+/// Layout of the pixels of an image in memory.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// One byte of luminance per pixel.
+    Gray8,
+    /// One byte of luminance followed by one byte of alpha per pixel.
+    GrayAlpha8,
+    /// Red, green and blue bytes per pixel, fully opaque.
+    Rgb8,
+    /// Red, green, blue and alpha bytes per pixel.
+    #[default]
+    Rgba8,
+    /// Blue, green, red and alpha bytes per pixel.
+    Bgra8,
+    /// Red, green, blue and alpha bytes per pixel, with the color
+    /// components already multiplied by alpha.
+    PremultipliedRgba8,
+}
+
+impl PixelFormat {
+    /// Number of bytes used by one pixel.
+    pub fn bytes_per_pixel(self) -> u32 {
+        match self {
+            PixelFormat::Gray8 => 1,
+            PixelFormat::GrayAlpha8 => 2,
+            PixelFormat::Rgb8 => 3,
+            PixelFormat::Rgba8 | PixelFormat::Bgra8 | PixelFormat::PremultipliedRgba8 => 4,
+        }
+    }
+
+    /// Whether the color components are multiplied by alpha.
+    pub fn is_premultiplied(self) -> bool {
+        self == PixelFormat::PremultipliedRgba8
+    }
+}
+
+/// Pixels of an image, as they are laid out in memory.
+///
+/// Rows are `stride` bytes apart, which may be more than `width` pixels
+/// when rows are padded.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageData<'a> {
+    pub format: PixelFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Number of bytes between the start of two consecutive rows.
+    pub stride: u32,
+    pub pixels: &'a [u8],
+}
+
+impl<'a> ImageData<'a> {
+    /// Describe tightly packed pixels of a given format.
+    pub fn new(format: PixelFormat, width: u32, height: u32, pixels: &'a [u8]) -> Self {
+        Self {
+            format,
+            width,
+            height,
+            stride: width * format.bytes_per_pixel(),
+            pixels,
+        }
+    }
+
+    /// Describe tightly packed RGBA pixels.
+    pub fn rgba(width: u32, height: u32, pixels: &'a [u8]) -> Self {
+        Self::new(PixelFormat::Rgba8, width, height, pixels)
+    }
+
+    /// Set the number of bytes between the start of two consecutive rows.
+    pub fn with_stride(mut self, stride: u32) -> Self {
+        self.stride = stride;
+        self
+    }
+
+    /// Number of bytes of a row that are actually part of the image.
+    pub fn row_len(&self) -> usize {
+        (self.width * self.format.bytes_per_pixel()) as usize
+    }
+
+    /// Get a row of pixels, without its padding.
+    pub fn row(&self, y: u32) -> &'a [u8] {
+        let start = (y * self.stride) as usize;
+        &self.pixels[start..start + self.row_len()]
+    }
+
+    /// Whether the pixel buffer is large enough for the described image.
+    pub fn is_valid(&self) -> bool {
+        if self.width == 0 || self.height == 0 {
+            return true;
+        }
+
+        let needed = (self.height - 1) as usize * self.stride as usize + self.row_len();
+        self.stride as usize >= self.row_len() && self.pixels.len() >= needed
+    }
+
+    /// Convert the pixels to tightly packed RGBA.
+    ///
+    /// Color components are multiplied by alpha if `premultiplied` is
+    /// `true`, and divided by it if the source is premultiplied but
+    /// `premultiplied` is `false`.
+    pub fn to_rgba(&self, premultiplied: bool) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity((self.width * self.height * 4) as usize);
+        let bpp = self.format.bytes_per_pixel() as usize;
+        let convert: fn(u8, u8) -> u8 = match (self.format.is_premultiplied(), premultiplied) {
+            (false, true) => premultiply,
+            (true, false) => unpremultiply,
+            _ => |c, _| c,
+        };
+
+        for y in 0..self.height {
+            for px in self.row(y).chunks_exact(bpp) {
+                let [r, g, b, a] = match self.format {
+                    PixelFormat::Gray8 => [px[0], px[0], px[0], 255],
+                    PixelFormat::GrayAlpha8 => [px[0], px[0], px[0], px[1]],
+                    PixelFormat::Rgb8 => [px[0], px[1], px[2], 255],
+                    PixelFormat::Rgba8 | PixelFormat::PremultipliedRgba8 => {
+                        [px[0], px[1], px[2], px[3]]
+                    }
+                    PixelFormat::Bgra8 => [px[2], px[1], px[0], px[3]],
+                };
+
+                rgba.extend([convert(r, a), convert(g, a), convert(b, a), a]);
+            }
+        }
+
+        rgba
+    }
+}
+
+fn premultiply(c: u8, a: u8) -> u8 {
+    ((c as u32 * a as u32 + 127) / 255) as u8
+}
+
+fn unpremultiply(c: u8, a: u8) -> u8 {
+    if a == 0 {
+        0
+    } else {
+        ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_all_formats_to_rgba() {
+        let convert = |format, pixels: &[u8]| ImageData::new(format, 1, 1, pixels).to_rgba(false);
+
+        assert_eq!(convert(PixelFormat::Gray8, &[7]), [7, 7, 7, 255]);
+        assert_eq!(convert(PixelFormat::GrayAlpha8, &[7, 9]), [7, 7, 7, 9]);
+        assert_eq!(convert(PixelFormat::Rgb8, &[1, 2, 3]), [1, 2, 3, 255]);
+        assert_eq!(convert(PixelFormat::Rgba8, &[1, 2, 3, 4]), [1, 2, 3, 4]);
+        assert_eq!(convert(PixelFormat::Bgra8, &[1, 2, 3, 4]), [3, 2, 1, 4]);
+    }
+
+    #[test]
+    fn skips_row_padding() {
+        let pixels = [1, 2, 3, 4, 5, 6, 0, 0, 7, 8, 9, 10, 11, 12, 0, 0];
+        let data = ImageData::new(PixelFormat::Rgb8, 2, 2, &pixels).with_stride(8);
+
+        assert!(data.is_valid());
+        assert_eq!(
+            data.to_rgba(false),
+            [1, 2, 3, 255, 4, 5, 6, 255, 7, 8, 9, 255, 10, 11, 12, 255]
+        );
+    }
+
+    #[test]
+    fn premultiplies_and_unpremultiplies() {
+        let straight = ImageData::rgba(2, 1, &[255, 128, 0, 128, 10, 20, 30, 0]);
+        assert_eq!(straight.to_rgba(true), [128, 64, 0, 128, 0, 0, 0, 0]);
+
+        let premultiplied = ImageData::new(
+            PixelFormat::PremultipliedRgba8,
+            2,
+            1,
+            &[128, 64, 0, 128, 0, 0, 0, 0],
+        );
+        assert_eq!(premultiplied.to_rgba(false), [255, 128, 0, 128, 0, 0, 0, 0]);
+        assert_eq!(premultiplied.to_rgba(true), [128, 64, 0, 128, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn checks_the_buffer_size() {
+        assert!(!ImageData::rgba(2, 2, &[0; 15]).is_valid());
+        assert!(ImageData::rgba(2, 2, &[0; 16]).is_valid());
+        assert!(!ImageData::rgba(2, 1, &[0; 8]).with_stride(4).is_valid());
+        assert!(ImageData::rgba(0, 0, &[]).is_valid());
+    }
+}
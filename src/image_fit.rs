@@ -0,0 +1,173 @@
+use crate::rect::Rect;
+
+/// How an image is fitted into a destination rectangle.
+///
+/// This works like the CSS `object-fit` property.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFit {
+    /// Stretch the image to fill the rectangle, ignoring its aspect ratio.
+    #[default]
+    Fill,
+    /// Scale the image to fit inside the rectangle, keeping its aspect ratio.
+    ///
+    /// The image may not cover the whole rectangle.
+    Contain,
+    /// Scale the image to cover the whole rectangle, keeping its aspect ratio.
+    ///
+    /// The parts of the image that fall outside the rectangle are cropped.
+    Cover,
+    /// Like [`Contain`](ImageFit::Contain), but never scales the image up.
+    ScaleDown,
+}
+
+/// Alignment anchor of an image inside its destination rectangle.
+///
+/// Both components go from `0.0` (left/top) to `1.0` (right/bottom).
+/// For [`ImageFit::Cover`], this decides which part of the image is kept
+/// when cropping.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageAlign {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl ImageAlign {
+    pub const TOP_LEFT: Self = Self::new(0.0, 0.0);
+    pub const TOP: Self = Self::new(0.5, 0.0);
+    pub const TOP_RIGHT: Self = Self::new(1.0, 0.0);
+    pub const LEFT: Self = Self::new(0.0, 0.5);
+    pub const CENTER: Self = Self::new(0.5, 0.5);
+    pub const RIGHT: Self = Self::new(1.0, 0.5);
+    pub const BOTTOM_LEFT: Self = Self::new(0.0, 1.0);
+    pub const BOTTOM: Self = Self::new(0.5, 1.0);
+    pub const BOTTOM_RIGHT: Self = Self::new(1.0, 1.0);
+
+    pub const fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+}
+
+impl Default for ImageAlign {
+    fn default() -> Self {
+        Self::CENTER
+    }
+}
+
+impl ImageFit {
+    /// Compute where to draw an image of a given size inside `target`.
+    ///
+    /// Returns the destination rectangle on screen and the source
+    /// rectangle in image pixels, in that order.
+    pub fn compute(
+        self,
+        image_width: f32,
+        image_height: f32,
+        target: &Rect,
+        align: ImageAlign,
+    ) -> (Rect, Rect) {
+        let full = Rect::new(0., 0., image_width, image_height);
+
+        if image_width <= 0. || image_height <= 0. {
+            return (*target, full);
+        }
+
+        let scale_x = target.w / image_width;
+        let scale_y = target.h / image_height;
+
+        let scale = match self {
+            ImageFit::Fill => return (*target, full),
+            ImageFit::Contain => scale_x.min(scale_y),
+            ImageFit::ScaleDown => scale_x.min(scale_y).min(1.0),
+            ImageFit::Cover => {
+                let scale = scale_x.max(scale_y);
+                let src_w = target.w / scale;
+                let src_h = target.h / scale;
+                let src = Rect::new(
+                    (image_width - src_w) * align.x,
+                    (image_height - src_h) * align.y,
+                    src_w,
+                    src_h,
+                );
+
+                return (*target, src);
+            }
+        };
+
+        let w = image_width * scale;
+        let h = image_height * scale;
+        let dst = Rect::new(
+            target.x + (target.w - w) * align.x,
+            target.y + (target.h - h) * align.y,
+            w,
+            h,
+        );
+
+        (dst, full)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(r: Rect) -> [f32; 4] {
+        [r.x, r.y, r.w, r.h]
+    }
+
+    const TARGET: Rect = Rect {
+        x: 10.,
+        y: 20.,
+        w: 200.,
+        h: 100.,
+    };
+
+    #[test]
+    fn fill_stretches_the_whole_image() {
+        let (dst, src) = ImageFit::Fill.compute(50., 50., &TARGET, ImageAlign::CENTER);
+        assert_eq!(rect(dst), rect(TARGET));
+        assert_eq!(rect(src), [0., 0., 50., 50.]);
+    }
+
+    #[test]
+    fn contain_fits_inside_and_aligns() {
+        let fit = |align| ImageFit::Contain.compute(50., 50., &TARGET, align);
+
+        let (dst, src) = fit(ImageAlign::CENTER);
+        assert_eq!(rect(dst), [60., 20., 100., 100.]);
+        assert_eq!(rect(src), [0., 0., 50., 50.]);
+
+        assert_eq!(rect(fit(ImageAlign::LEFT).0), [10., 20., 100., 100.]);
+        assert_eq!(
+            rect(fit(ImageAlign::BOTTOM_RIGHT).0),
+            [110., 20., 100., 100.]
+        );
+    }
+
+    #[test]
+    fn cover_crops_the_image_around_the_anchor() {
+        let fit = |align| ImageFit::Cover.compute(50., 50., &TARGET, align);
+
+        let (dst, src) = fit(ImageAlign::CENTER);
+        assert_eq!(rect(dst), rect(TARGET));
+        assert_eq!(rect(src), [0., 12.5, 50., 25.]);
+
+        assert_eq!(rect(fit(ImageAlign::TOP).1), [0., 0., 50., 25.]);
+        assert_eq!(rect(fit(ImageAlign::BOTTOM).1), [0., 25., 50., 25.]);
+    }
+
+    #[test]
+    fn scale_down_never_enlarges() {
+        let (dst, _) = ImageFit::ScaleDown.compute(50., 20., &TARGET, ImageAlign::TOP_LEFT);
+        assert_eq!(rect(dst), [10., 20., 50., 20.]);
+
+        let (dst, _) = ImageFit::ScaleDown.compute(400., 100., &TARGET, ImageAlign::TOP_LEFT);
+        assert_eq!(rect(dst), [10., 20., 200., 50.]);
+    }
+
+    #[test]
+    fn empty_images_fill_the_target() {
+        let (dst, src) = ImageFit::Cover.compute(0., 50., &TARGET, ImageAlign::CENTER);
+        assert_eq!(rect(dst), rect(TARGET));
+        assert_eq!(rect(src), [0., 0., 0., 50.]);
+    }
+}
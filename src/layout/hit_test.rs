@@ -0,0 +1,272 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::rect::Rect;
+
+use super::{is_line_terminator, TextLayout};
+
+/// The horizontal extent of a grapheme on its line.
+struct GraphemeBox {
+    text: Range<usize>,
+    left: f32,
+    right: f32,
+    rtl: bool,
+}
+
+impl GraphemeBox {
+    /// Position of a caret placed before the grapheme.
+    fn leading(&self) -> f32 {
+        if self.rtl {
+            self.right
+        } else {
+            self.left
+        }
+    }
+
+    /// Position of a caret placed after the grapheme.
+    fn trailing(&self) -> f32 {
+        if self.rtl {
+            self.left
+        } else {
+            self.right
+        }
+    }
+}
+
+/// Queries mapping between positions in a layout and indices in its text.
+///
+/// Indices are byte indices on grapheme boundaries. `text` must be the
+/// text the layout was made from.
+impl TextLayout {
+    /// Index of the grapheme boundary closest to a point.
+    pub fn index_at(&self, text: &str, x: f32, y: f32) -> usize {
+        let line = self
+            .lines
+            .iter()
+            .position(|line| y < line.top + line.height)
+            .or(self.lines.len().checked_sub(1));
+
+        let Some(line) = line else {
+            return 0;
+        };
+
+        let boxes = self.grapheme_boxes(text, line);
+        let distance = |b: &GraphemeBox| (b.left - x).max(x - b.right).max(0.0);
+        let Some(hit) = boxes
+            .iter()
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+        else {
+            return self.lines[line].text.start;
+        };
+
+        // Which half of the grapheme is hit decides which side the caret goes.
+        let before_middle = x < (hit.left + hit.right) / 2.0;
+        if before_middle != hit.rtl {
+            hit.text.start
+        } else {
+            hit.text.end
+        }
+    }
+
+    /// Zero-width rectangle of the caret placed at an index, spanning the
+    /// height of its line.
+    ///
+    /// An index at the boundary between two wrapped lines is placed at the
+    /// start of the second line.
+    pub fn caret_rect(&self, text: &str, index: usize) -> Rect {
+        let Some(line) = self.lines.iter().rposition(|line| line.text.start <= index) else {
+            return Rect::new(0., 0., 0., 0.);
+        };
+
+        let boxes = self.grapheme_boxes(text, line);
+        let line = &self.lines[line];
+
+        let x = match boxes.iter().find(|b| b.text.end > index) {
+            Some(b) => b.leading(),
+            None => boxes.last().map_or(line.x, GraphemeBox::trailing),
+        };
+
+        Rect::new(x, line.top, 0., line.height)
+    }
+
+    /// Rectangles covering the graphemes of a range of the text, at most
+    /// a few per line when the range mixes directions.
+    pub fn selection_rects(&self, text: &str, range: Range<usize>) -> Vec<Rect> {
+        let mut rects = Vec::new();
+
+        for (i, line) in self.lines.iter().enumerate() {
+            if line.text.end <= range.start || line.text.start >= range.end {
+                continue;
+            }
+
+            let mut boxes = self.grapheme_boxes(text, i);
+            boxes.sort_by(|a, b| a.left.total_cmp(&b.left));
+
+            // Merge visually adjacent selected graphemes.
+            let mut current: Option<(f32, f32)> = None;
+            for b in &boxes {
+                let selected = b.text.start < range.end && b.text.end > range.start;
+
+                match (selected, &mut current) {
+                    (true, Some((_, right))) => *right = b.right,
+                    (true, None) => current = Some((b.left, b.right)),
+                    (false, Some((left, right))) => {
+                        rects.push(Rect::new(*left, line.top, *right - *left, line.height));
+                        current = None;
+                    }
+                    (false, None) => (),
+                }
+            }
+
+            if let Some((left, right)) = current {
+                rects.push(Rect::new(left, line.top, right - left, line.height));
+            }
+        }
+
+        rects
+    }
+
+    /// Boxes of the graphemes of a line, in logical order.
+    ///
+    /// Glyphs covering several graphemes, like ligatures, are split evenly
+    /// between them. Inline objects make a single box.
+    fn grapheme_boxes(&self, text: &str, line: usize) -> Vec<GraphemeBox> {
+        let line = &self.lines[line];
+        let end = line.text.start
+            + text[line.text.clone()]
+                .trim_end_matches(is_line_terminator)
+                .len();
+
+        let mut clusters: BTreeMap<usize, (f32, f32, bool, bool)> = BTreeMap::new();
+        for glyph in &self.glyphs[line.glyphs.clone()] {
+            if glyph.cluster >= end {
+                continue;
+            }
+
+            let extent = clusters.entry(glyph.cluster).or_insert((
+                f32::INFINITY,
+                f32::NEG_INFINITY,
+                glyph.rtl,
+                glyph.object,
+            ));
+            extent.0 = extent.0.min(glyph.x);
+            extent.1 = extent.1.max(glyph.x + glyph.advance);
+        }
+
+        let starts: Vec<usize> = clusters.keys().copied().collect();
+        let mut boxes = Vec::new();
+
+        for (k, (&start, &(left, right, rtl, object))) in clusters.iter().enumerate() {
+            let cluster_end = starts.get(k + 1).copied().unwrap_or(end);
+            let graphemes: Vec<Range<usize>> = match object {
+                true => std::iter::once(start..cluster_end).collect(),
+                false => text[start..cluster_end]
+                    .grapheme_indices(true)
+                    .map(|(i, g)| start + i..start + i + g.len())
+                    .collect(),
+            };
+
+            let step = (right - left) / graphemes.len() as f32;
+            let count = graphemes.len();
+
+            for (n, range) in graphemes.into_iter().enumerate() {
+                let n = if rtl { count - 1 - n } else { n } as f32;
+
+                boxes.push(GraphemeBox {
+                    text: range,
+                    left: left + n * step,
+                    right: left + (n + 1.0) * step,
+                    rtl,
+                });
+            }
+        }
+
+        boxes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{layout, roboto};
+    use super::super::LayoutOptions;
+
+    #[test]
+    fn places_carets_at_grapheme_edges() {
+        let font = roboto();
+        let text = "abc";
+        let layout = layout(&font, text, LayoutOptions::default());
+        let line = &layout.lines[0];
+
+        let start = layout.caret_rect(text, 0);
+        assert_eq!((start.x, start.y, start.w), (0.0, line.top, 0.0));
+        assert_eq!(start.h, line.height);
+
+        let second = layout.caret_rect(text, 1);
+        assert_eq!(second.x, layout.glyphs[1].x);
+
+        let end = layout.caret_rect(text, text.len());
+        assert!((end.x - layout.width).abs() < 1e-3);
+    }
+
+    #[test]
+    fn hits_the_closest_grapheme_boundary() {
+        let font = roboto();
+        let text = "abc";
+        let layout = layout(&font, text, LayoutOptions::default());
+        let a = &layout.glyphs[0];
+        let y = layout.lines[0].baseline;
+
+        assert_eq!(layout.index_at(text, a.x + a.advance * 0.25, y), 0);
+        assert_eq!(layout.index_at(text, a.x + a.advance * 0.75, y), 1);
+        assert_eq!(layout.index_at(text, -100.0, y), 0);
+        assert_eq!(layout.index_at(text, 1000.0, y), 3);
+    }
+
+    #[test]
+    fn hits_lines_by_height() {
+        let font = roboto();
+        let text = "ab\ncd";
+        let layout = layout(&font, text, LayoutOptions::default());
+        let second = &layout.lines[1];
+
+        assert_eq!(layout.index_at(text, 0.0, second.top + 1.0), 3);
+        assert_eq!(layout.index_at(text, 0.0, layout.height + 100.0), 3);
+        assert_eq!(layout.index_at(text, 0.0, -100.0), 0);
+        assert_eq!(layout.caret_rect(text, 4).y, second.top);
+    }
+
+    #[test]
+    fn treats_right_to_left_graphemes_as_mirrored() {
+        let font = roboto();
+        // "אב", laid out right to left.
+        let text = "\u{5d0}\u{5d1}";
+        let layout = layout(&font, text, LayoutOptions::default());
+        let y = layout.lines[0].baseline;
+
+        assert!((layout.caret_rect(text, 0).x - layout.width).abs() < 1e-3);
+        assert!(layout.caret_rect(text, text.len()).x.abs() < 1e-3);
+        assert_eq!(layout.index_at(text, layout.width - 0.1, y), 0);
+    }
+
+    #[test]
+    fn covers_selections_with_a_rect_per_line() {
+        let font = roboto();
+        let text = "ab\ncd";
+        let layout = layout(&font, text, LayoutOptions::default());
+
+        let rects = layout.selection_rects(text, 0..text.len());
+        assert_eq!(rects.len(), 2);
+        for (rect, line) in rects.iter().zip(&layout.lines) {
+            assert_eq!((rect.y, rect.h), (line.top, line.height));
+            assert!((rect.w - line.width).abs() < 1e-3);
+        }
+
+        let partial = layout.selection_rects(text, 1..2);
+        assert_eq!(partial.len(), 1);
+        assert_eq!(partial[0].x, layout.glyphs[1].x);
+
+        assert!(layout.selection_rects(text, 1..1).is_empty());
+    }
+}
@@ -0,0 +1,1154 @@
+use std::ops::Range;
+
+use rusttype::{GlyphId, Scale};
+use unicode_bidi::{BidiInfo, Level};
+use unicode_linebreak::{linebreaks, BreakOpportunity};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::font::FontStack;
+
+mod hit_test;
+
+/// Horizontal alignment of the lines of a text.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TextAlign {
+    /// Align to the start of each paragraph: the left for left-to-right
+    /// paragraphs and the right for right-to-left ones.
+    #[default]
+    Start,
+    /// Align to the end of each paragraph.
+    End,
+    /// Align to the left, whatever the paragraph direction.
+    Left,
+    Center,
+    /// Align to the right, whatever the paragraph direction.
+    Right,
+    /// Stretch the spaces of wrapped lines so that they fill the maximum
+    /// width. The last line of each paragraph is aligned to its start.
+    Justify,
+}
+
+/// How text that doesn't fit in the layout is cut.
+///
+/// Text is cut between graphemes and the removed part is replaced with an
+/// ellipsis, whose glyph has the cluster of the first character it hides.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Truncate {
+    #[default]
+    None,
+    /// Cut the end of the last line, so that the text fits in `max_lines`
+    /// lines. Only a single line is kept when `max_lines` is `None`.
+    End,
+    /// Cut the middle of lines wider than `max_width`, keeping their start
+    /// and end. Lines are not wrapped. Useful for file names.
+    Middle,
+}
+
+/// A range of a text laid out with the same fonts and size.
+#[derive(Clone, Copy)]
+pub struct LayoutSpan<'a> {
+    /// Start of the span in the text, in bytes. The span ends where the
+    /// next one starts.
+    pub start: usize,
+    pub fonts: FontStack<'a>,
+    pub size: f32,
+    /// Lay out the span as a single glyph of this size instead of shaping
+    /// its text.
+    pub object: Option<InlineObject>,
+}
+
+/// The size of something placed inline like a character, like an image.
+///
+/// It is never broken across lines, and selected as a whole.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InlineObject {
+    pub advance: f32,
+    /// Height above the baseline.
+    pub ascent: f32,
+    /// Height below the baseline.
+    pub descent: f32,
+}
+
+/// Options controlling how a text is laid out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LayoutOptions {
+    /// Width at which lines are wrapped. Lines are only broken at explicit
+    /// newlines when this is `None`.
+    pub max_width: Option<f32>,
+    pub align: TextAlign,
+    /// Line height, as a multiple of the natural line height of the fonts.
+    pub line_height: f32,
+    /// Maximum number of lines. Lines past this limit are dropped.
+    pub max_lines: Option<usize>,
+    pub truncate: Truncate,
+}
+
+impl Default for LayoutOptions {
+    fn default() -> Self {
+        Self {
+            max_width: None,
+            align: TextAlign::Start,
+            line_height: 1.0,
+            max_lines: None,
+            truncate: Truncate::None,
+        }
+    }
+}
+
+/// A glyph placed by the layout engine.
+///
+/// The glyphs of each line are stored in visual order, from left to right.
+#[derive(Debug, Clone, Copy)]
+pub struct LayoutGlyph {
+    /// Index of the span of the glyph.
+    pub span: usize,
+    /// Index of the font of the glyph in the [`FontStack`] of its span.
+    pub font: usize,
+    pub id: GlyphId,
+    /// Byte index in the text of the first character this glyph comes from.
+    pub cluster: usize,
+    /// Horizontal position of the glyph origin, relative to the layout.
+    pub x: f32,
+    /// Vertical position of the glyph baseline, relative to the layout.
+    pub y: f32,
+    pub advance: f32,
+    /// Whether the glyph is part of right-to-left text.
+    pub rtl: bool,
+    /// Whether the glyph stands for the [`InlineObject`] of its span, in
+    /// which case `font` and `id` are meaningless.
+    pub object: bool,
+}
+
+/// A line of a laid out text.
+#[derive(Debug, Clone)]
+pub struct LayoutLine {
+    /// Range of the glyphs of this line in [`TextLayout::glyphs`].
+    pub glyphs: Range<usize>,
+    /// Range of the text covered by this line, in bytes.
+    ///
+    /// Consecutive lines cover consecutive ranges, including the spaces
+    /// and newlines where they were broken.
+    pub text: Range<usize>,
+    /// Horizontal position of the start of the line, after alignment.
+    pub x: f32,
+    pub top: f32,
+    pub baseline: f32,
+    /// Width of the line, not counting trailing spaces.
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Consecutive glyphs of a line coming from the same span.
+#[derive(Debug, Clone)]
+pub struct LayoutRun {
+    pub span: usize,
+    pub line: usize,
+    /// Range of the glyphs of this run in [`TextLayout::glyphs`].
+    pub glyphs: Range<usize>,
+    pub x: f32,
+    pub width: f32,
+}
+
+/// A text laid out into lines of positioned glyphs.
+///
+/// Positions are relative to the top-left corner of the layout and given
+/// in the same unit as the font size.
+#[derive(Debug, Default, Clone)]
+pub struct TextLayout {
+    pub glyphs: Vec<LayoutGlyph>,
+    pub lines: Vec<LayoutLine>,
+    /// Runs of glyphs of the same span, in visual order within each line.
+    pub runs: Vec<LayoutRun>,
+    /// Width of the longest line.
+    pub width: f32,
+    /// Height of all lines together.
+    pub height: f32,
+}
+
+/// A glyph with its advance, before being placed on a line.
+#[derive(Clone, Copy)]
+struct ShapedGlyph {
+    span: usize,
+    font: usize,
+    id: GlyphId,
+    cluster: usize,
+    advance: f32,
+    offset: (f32, f32),
+    /// Bidirectional embedding level of the glyph.
+    level: Level,
+    whitespace: bool,
+    object: bool,
+}
+
+/// Where a line ends in the text and in the shaped glyphs.
+struct LineBreak {
+    text: Range<usize>,
+    glyphs: Range<usize>,
+    /// Whether the line ends a paragraph rather than being wrapped.
+    mandatory: bool,
+}
+
+/// A text being laid out, with its spans and bidirectional levels.
+struct Source<'a> {
+    text: &'a str,
+    spans: &'a [LayoutSpan<'a>],
+    bidi: BidiInfo<'a>,
+}
+
+impl<'a> Source<'a> {
+    fn new(text: &'a str, spans: &'a [LayoutSpan<'a>]) -> Self {
+        Self {
+            text,
+            spans,
+            bidi: BidiInfo::new(text, None),
+        }
+    }
+
+    /// Index of the span containing a byte index of the text.
+    fn span_at(&self, index: usize) -> usize {
+        self.spans
+            .partition_point(|span| span.start <= index)
+            .saturating_sub(1)
+    }
+
+    /// Byte index of the end of a span.
+    fn span_end(&self, span: usize) -> usize {
+        self.spans
+            .get(span + 1)
+            .map_or(self.text.len(), |next| next.start)
+    }
+
+    /// Whether a byte index is within the text of an inline object, rather
+    /// than at its start or end.
+    fn inside_object(&self, index: usize) -> bool {
+        let span = self.span_at(index);
+        self.spans[span].object.is_some()
+            && self.spans[span].start < index
+            && index < self.span_end(span)
+    }
+
+    /// Level of the paragraph containing a byte index of the text.
+    fn paragraph_level(&self, index: usize) -> Level {
+        self.bidi
+            .paragraphs
+            .iter()
+            .find(|paragraph| paragraph.range.contains(&index))
+            .map_or(Level::ltr(), |paragraph| paragraph.level)
+    }
+}
+
+impl TextLayout {
+    /// Lay out a text, picking a font of the stack for each grapheme.
+    ///
+    /// The direction of each paragraph is given by its first strong
+    /// character, as per the Unicode bidirectional algorithm.
+    pub fn new(fonts: FontStack, text: &str, size: f32, options: &LayoutOptions) -> Self {
+        let spans = [LayoutSpan {
+            start: 0,
+            fonts,
+            size,
+            object: None,
+        }];
+
+        Self::from_spans(text, &spans, options)
+    }
+
+    /// Lay out a text made of spans with different fonts and sizes.
+    ///
+    /// Spans share lines and are aligned on a common baseline. The first
+    /// span must start at `0`. Without spans, the layout is empty.
+    pub fn from_spans(text: &str, spans: &[LayoutSpan], options: &LayoutOptions) -> Self {
+        if spans.is_empty() {
+            return Self::default();
+        }
+
+        let source = Source::new(text, spans);
+        let glyphs = shape(&source);
+
+        let (glyphs, breaks) = match options.truncate {
+            Truncate::None => {
+                let breaks = break_lines(&source, &glyphs, options.max_width);
+                (glyphs, breaks)
+            }
+            Truncate::End => truncate_end(&source, glyphs, options),
+            Truncate::Middle => truncate_middle(&source, glyphs, options),
+        };
+
+        position(&source, &glyphs, &breaks, options)
+    }
+}
+
+fn is_line_terminator(c: char) -> bool {
+    matches!(
+        c,
+        '\n' | '\r' | '\u{0b}' | '\u{0c}' | '\u{85}' | '\u{2028}' | '\u{2029}'
+    )
+}
+
+/// A run of text shaped at once.
+#[derive(PartialEq)]
+struct ShapingRun {
+    text: Range<usize>,
+    span: usize,
+    font: usize,
+    level: Level,
+}
+
+/// Split a text into runs drawn with the same span, font and direction,
+/// leaving out line terminators.
+fn shaping_runs(source: &Source) -> Vec<ShapingRun> {
+    let mut runs: Vec<ShapingRun> = Vec::new();
+
+    for (start, grapheme) in source.text.grapheme_indices(true) {
+        if grapheme.starts_with(is_line_terminator) {
+            continue;
+        }
+
+        let span = source.span_at(start);
+        let fonts = source.spans[span].fonts;
+
+        // An inline object stands for all the text of its span.
+        if source.spans[span].object.is_some() {
+            if runs.last().is_none_or(|last| last.span != span) {
+                runs.push(ShapingRun {
+                    text: start..source.span_end(span),
+                    span,
+                    font: 0,
+                    level: source.bidi.levels[start],
+                });
+            }
+            continue;
+        }
+
+        // All characters of a grapheme come from the same font, so that
+        // combining marks stay with their base character.
+        let first = grapheme.chars().next().unwrap();
+        let font = fonts
+            .font_for(grapheme)
+            .or_else(|| fonts.font_for(&grapheme[..first.len_utf8()]))
+            .unwrap_or(0);
+
+        let run = ShapingRun {
+            text: start..start + grapheme.len(),
+            span,
+            font,
+            level: source.bidi.levels[start],
+        };
+
+        match runs.last_mut() {
+            Some(last)
+                if last.text.end == start
+                    && (last.span, last.font, last.level) == (span, font, run.level) =>
+            {
+                last.text.end = run.text.end
+            }
+            _ => runs.push(run),
+        }
+    }
+
+    runs
+}
+
+fn shape(source: &Source) -> Vec<ShapedGlyph> {
+    let text = source.text;
+    let mut glyphs = Vec::with_capacity(text.len());
+
+    for run in shaping_runs(source) {
+        let LayoutSpan {
+            fonts,
+            size,
+            object,
+            ..
+        } = source.spans[run.span];
+        let (font, level) = (run.font, run.level);
+
+        if let Some(object) = object {
+            glyphs.push(ShapedGlyph {
+                span: run.span,
+                font,
+                id: GlyphId(0),
+                cluster: run.text.start,
+                advance: object.advance,
+                offset: (0.0, 0.0),
+                level,
+                whitespace: false,
+                object: true,
+            });
+            continue;
+        }
+
+        let run_glyphs = fonts
+            .get(font)
+            .shape(&text[run.text.clone()], size, level.is_rtl());
+
+        for glyph in run_glyphs {
+            let cluster = run.text.start + glyph.cluster;
+
+            glyphs.push(ShapedGlyph {
+                span: run.span,
+                font,
+                id: glyph.id,
+                cluster,
+                advance: glyph.advance,
+                offset: glyph.offset,
+                level,
+                whitespace: text[cluster..].starts_with(char::is_whitespace),
+                object: false,
+            });
+        }
+    }
+
+    glyphs
+}
+
+fn advance_sum(glyphs: &[ShapedGlyph]) -> f32 {
+    glyphs.iter().map(|g| g.advance).sum()
+}
+
+/// Width of the spaces at the end of a run of glyphs.
+fn trailing_whitespace(glyphs: &[ShapedGlyph]) -> f32 {
+    glyphs
+        .iter()
+        .rev()
+        .take_while(|g| g.whitespace)
+        .map(|g| g.advance)
+        .sum()
+}
+
+/// Greedily fills lines, breaking at the line break opportunities of the
+/// text and falling back to grapheme boundaries for words that don't fit
+/// on a line by themselves.
+struct LineBreaker<'a> {
+    text: &'a str,
+    glyphs: &'a [ShapedGlyph],
+    lines: Vec<LineBreak>,
+    /// Start of the current line in the text and in the glyphs.
+    start: (usize, usize),
+    width: f32,
+}
+
+impl LineBreaker<'_> {
+    /// Index of the first glyph at or after a byte index of the text.
+    fn glyph_at(&self, index: usize) -> usize {
+        self.glyphs.partition_point(|g| g.cluster < index)
+    }
+
+    fn is_empty(&self, glyph: usize) -> bool {
+        glyph == self.start.1
+    }
+
+    fn push(&mut self, end: usize, mandatory: bool) {
+        let glyph_end = self.glyph_at(end);
+
+        self.lines.push(LineBreak {
+            text: self.start.0..end,
+            glyphs: self.start.1..glyph_end,
+            mandatory,
+        });
+
+        self.start = (end, glyph_end);
+        self.width = 0.0;
+    }
+
+    /// Add the segment of text between two break opportunities.
+    fn add_segment(&mut self, segment: Range<usize>, max_width: Option<f32>) {
+        let glyphs = self.glyph_at(segment.start)..self.glyph_at(segment.end);
+        let width = advance_sum(&self.glyphs[glyphs.clone()]);
+        let trailing = trailing_whitespace(&self.glyphs[glyphs.clone()]);
+
+        if let Some(max_width) = max_width {
+            if !self.is_empty(glyphs.start) && self.width + width - trailing > max_width {
+                self.push(segment.start, false);
+            }
+
+            if self.is_empty(glyphs.start) && width - trailing > max_width {
+                self.add_graphemes(segment, max_width);
+                return;
+            }
+        }
+
+        self.width += width;
+    }
+
+    /// Add a segment too long to fit on a line, breaking it between graphemes.
+    fn add_graphemes(&mut self, segment: Range<usize>, max_width: f32) {
+        for (i, grapheme) in self.text[segment.clone()].grapheme_indices(true) {
+            let start = segment.start + i;
+            let glyphs = self.glyph_at(start)..self.glyph_at(start + grapheme.len());
+            let width = advance_sum(&self.glyphs[glyphs.clone()]);
+            let whitespace = grapheme.chars().all(char::is_whitespace);
+
+            if !whitespace && !self.is_empty(glyphs.start) && self.width + width > max_width {
+                self.push(start, false);
+            }
+
+            self.width += width;
+        }
+    }
+}
+
+fn break_lines(source: &Source, glyphs: &[ShapedGlyph], max_width: Option<f32>) -> Vec<LineBreak> {
+    let text = source.text;
+    let mut breaker = LineBreaker {
+        text,
+        glyphs,
+        lines: Vec::new(),
+        start: (0, 0),
+        width: 0.0,
+    };
+
+    let mut segment_start = 0;
+    let opportunities = linebreaks(text).filter(|&(index, _)| !source.inside_object(index));
+    for (index, opportunity) in opportunities {
+        breaker.add_segment(segment_start..index, max_width);
+        segment_start = index;
+
+        if opportunity == BreakOpportunity::Mandatory {
+            breaker.push(index, true);
+        }
+    }
+
+    // A trailing newline starts one last, empty line.
+    if text.is_empty() || text.ends_with(is_line_terminator) {
+        breaker.push(text.len(), true);
+    }
+
+    breaker.lines
+}
+
+/// A grapheme of a line being truncated.
+struct Grapheme {
+    /// Byte index of the grapheme in the text.
+    text: usize,
+    /// Index of its first glyph.
+    glyph: usize,
+    width: f32,
+    whitespace: bool,
+}
+
+/// Cuts runs of glyphs between graphemes to make room for an ellipsis.
+struct Truncator<'a> {
+    text: &'a str,
+    source: &'a Source<'a>,
+    glyphs: &'a [ShapedGlyph],
+    /// The ellipsis shaped with the fonts of each span.
+    ellipses: Vec<Vec<ShapedGlyph>>,
+}
+
+impl<'a> Truncator<'a> {
+    fn new(source: &'a Source<'a>, glyphs: &'a [ShapedGlyph]) -> Self {
+        let ellipses = source
+            .spans
+            .iter()
+            .map(|span| {
+                let ellipsis = match span.fonts.font_for("\u{2026}") {
+                    Some(_) => "\u{2026}",
+                    None => "...",
+                };
+                let spans = [LayoutSpan {
+                    start: 0,
+                    object: None,
+                    ..*span
+                }];
+                shape(&Source::new(ellipsis, &spans))
+            })
+            .collect();
+
+        Self {
+            text: source.text,
+            source,
+            glyphs,
+            ellipses,
+        }
+    }
+
+    fn glyph_at(&self, index: usize) -> usize {
+        self.glyphs.partition_point(|g| g.cluster < index)
+    }
+
+    fn graphemes(&self, range: Range<usize>) -> Vec<Grapheme> {
+        self.text[range.clone()]
+            .grapheme_indices(true)
+            .map(|(i, grapheme)| {
+                let start = range.start + i;
+                let glyph = self.glyph_at(start);
+                let glyphs = &self.glyphs[glyph..self.glyph_at(start + grapheme.len())];
+
+                Grapheme {
+                    text: start,
+                    glyph,
+                    width: advance_sum(glyphs),
+                    whitespace: grapheme.chars().all(char::is_whitespace),
+                }
+            })
+            .collect()
+    }
+
+    /// Span of the ellipsis standing for the text starting at `cluster`,
+    /// which continues the text before it.
+    fn ellipsis_span(&self, cluster: usize) -> usize {
+        self.source.span_at(cluster.saturating_sub(1))
+    }
+
+    /// Push the ellipsis, standing for the text starting at `cluster`.
+    ///
+    /// The ellipsis takes the direction of its paragraph.
+    fn push_ellipsis(&self, out: &mut Vec<ShapedGlyph>, cluster: usize) {
+        let span = self.ellipsis_span(cluster);
+        let level = self.source.paragraph_level(cluster);
+        out.extend(self.ellipses[span].iter().map(|g| ShapedGlyph {
+            span,
+            cluster,
+            level,
+            ..*g
+        }));
+    }
+
+    /// Width kept for the ellipsis, the widest one of all spans.
+    fn ellipsis_width(&self) -> f32 {
+        self.ellipses
+            .iter()
+            .map(|ellipsis| advance_sum(ellipsis))
+            .fold(0.0, f32::max)
+    }
+
+    /// Push the start of a range of text that fits in `max_width` along
+    /// with an ellipsis.
+    fn cut_end(&self, out: &mut Vec<ShapedGlyph>, range: Range<usize>, max_width: f32) {
+        let graphemes = self.graphemes(range.clone());
+        let available = max_width - self.ellipsis_width();
+
+        let mut width = 0.0;
+        let mut kept = graphemes
+            .iter()
+            .take_while(|g| {
+                width += g.width;
+                width <= available
+            })
+            .count();
+
+        while kept > 0 && graphemes[kept - 1].whitespace {
+            kept -= 1;
+        }
+
+        let (cluster, glyph_end) = graphemes
+            .get(kept)
+            .map_or((range.end, self.glyph_at(range.end)), |g| (g.text, g.glyph));
+
+        out.extend_from_slice(&self.glyphs[self.glyph_at(range.start)..glyph_end]);
+        self.push_ellipsis(out, cluster);
+    }
+
+    /// Push the glyphs of a range of text, cutting its middle if it's wider
+    /// than `max_width`.
+    fn cut_middle(&self, out: &mut Vec<ShapedGlyph>, range: Range<usize>, max_width: f32) {
+        let glyphs = self.glyph_at(range.start)..self.glyph_at(range.end);
+        let run = &self.glyphs[glyphs.clone()];
+        if advance_sum(run) - trailing_whitespace(run) <= max_width {
+            out.extend_from_slice(run);
+            return;
+        }
+
+        let graphemes = self.graphemes(range.clone());
+        let available = max_width - self.ellipsis_width();
+
+        let mut head_width = 0.0;
+        let mut head = graphemes
+            .iter()
+            .take_while(|g| {
+                head_width += g.width;
+                head_width <= available / 2.0
+            })
+            .count();
+        head_width = graphemes[..head].iter().map(|g| g.width).sum();
+
+        let mut tail_width = 0.0;
+        let mut tail = graphemes.len()
+            - graphemes[head..]
+                .iter()
+                .rev()
+                .take_while(|g| {
+                    tail_width += g.width;
+                    head_width + tail_width <= available
+                })
+                .count();
+
+        while head > 0 && graphemes[head - 1].whitespace {
+            head -= 1;
+        }
+        while tail < graphemes.len() && graphemes[tail].whitespace {
+            tail += 1;
+        }
+
+        let (cluster, head_end) = graphemes
+            .get(head)
+            .map_or((range.end, glyphs.end), |g| (g.text, g.glyph));
+        let tail_start = graphemes.get(tail).map_or(glyphs.end, |g| g.glyph);
+
+        out.extend_from_slice(&self.glyphs[glyphs.start..head_end]);
+        self.push_ellipsis(out, cluster);
+        out.extend_from_slice(&self.glyphs[tail_start..glyphs.end]);
+    }
+}
+
+fn truncate_end(
+    source: &Source,
+    glyphs: Vec<ShapedGlyph>,
+    options: &LayoutOptions,
+) -> (Vec<ShapedGlyph>, Vec<LineBreak>) {
+    let text = source.text;
+    let max_lines = options.max_lines.unwrap_or(1);
+    let mut breaks = break_lines(source, &glyphs, options.max_width);
+
+    if breaks.len() <= max_lines || max_lines == 0 {
+        return (glyphs, breaks);
+    }
+
+    breaks.truncate(max_lines);
+    let last = breaks.last_mut().unwrap();
+
+    // The last line takes the rest of its paragraph, cut to fit.
+    let start = last.text.start;
+    let paragraph_end = text[start..]
+        .find(is_line_terminator)
+        .map_or(text.len(), |i| start + i);
+
+    let truncator = Truncator::new(source, &glyphs);
+    let mut out = glyphs[..last.glyphs.start].to_vec();
+    let max_width = options.max_width.unwrap_or(f32::INFINITY);
+    truncator.cut_end(&mut out, start..paragraph_end, max_width);
+
+    last.glyphs.end = out.len();
+    last.text.end = text.len();
+    last.mandatory = true;
+
+    (out, breaks)
+}
+
+fn truncate_middle(
+    source: &Source,
+    glyphs: Vec<ShapedGlyph>,
+    options: &LayoutOptions,
+) -> (Vec<ShapedGlyph>, Vec<LineBreak>) {
+    let mut breaks = break_lines(source, &glyphs, None);
+
+    let Some(max_width) = options.max_width else {
+        return (glyphs, breaks);
+    };
+
+    let truncator = Truncator::new(source, &glyphs);
+    let mut out = Vec::with_capacity(glyphs.len());
+
+    for line in &mut breaks {
+        let start = out.len();
+        if !line.glyphs.is_empty() {
+            truncator.cut_middle(&mut out, line.text.clone(), max_width);
+        }
+        line.glyphs = start..out.len();
+    }
+
+    (out, breaks)
+}
+
+/// Ascent, descent and line gap of the primary font of a span, or of its
+/// inline object.
+fn span_metrics(span: &LayoutSpan) -> [f32; 3] {
+    if let Some(object) = span.object {
+        return [object.ascent, object.descent, 0.0];
+    }
+
+    let font = span.fonts.primary();
+    let ascent = font.baseline(span.size);
+    let line_gap = font.get_v_advance(Scale::uniform(span.size)) - span.size;
+    [ascent, span.size - ascent, line_gap]
+}
+
+fn position(
+    source: &Source,
+    glyphs: &[ShapedGlyph],
+    breaks: &[LineBreak],
+    options: &LayoutOptions,
+) -> TextLayout {
+    let span_metrics: Vec<[f32; 3]> = source.spans.iter().map(span_metrics).collect();
+    let breaks = &breaks[..breaks.len().min(options.max_lines.unwrap_or(usize::MAX))];
+
+    let line_widths: Vec<f32> = breaks
+        .iter()
+        .map(|line| {
+            let glyphs = &glyphs[line.glyphs.clone()];
+            advance_sum(glyphs) - trailing_whitespace(glyphs)
+        })
+        .collect();
+
+    let width = line_widths.iter().copied().fold(0.0, f32::max);
+    let available = options.max_width.unwrap_or(width);
+
+    let mut layout_glyphs = Vec::with_capacity(glyphs.len());
+    let mut lines = Vec::with_capacity(breaks.len());
+    let mut runs: Vec<LayoutRun> = Vec::new();
+    let mut top = 0.0;
+
+    for (i, (line, &line_width)) in breaks.iter().zip(&line_widths).enumerate() {
+        let line_glyphs = &glyphs[line.glyphs.clone()];
+        let trailing = line_glyphs
+            .iter()
+            .rev()
+            .take_while(|g| g.whitespace)
+            .count();
+        let content = &line_glyphs[..line_glyphs.len() - trailing];
+        let spaces = content.iter().filter(|g| g.whitespace).count();
+        let extra = available - line_width;
+
+        let rtl = source.paragraph_level(line.text.start).is_rtl();
+        let (x, space_extra) = match options.align {
+            TextAlign::Start if rtl => (extra, 0.0),
+            TextAlign::End if !rtl => (extra, 0.0),
+            TextAlign::Start | TextAlign::End => (0.0, 0.0),
+            TextAlign::Left => (0.0, 0.0),
+            TextAlign::Center => (extra / 2.0, 0.0),
+            TextAlign::Right => (extra, 0.0),
+            TextAlign::Justify if !line.mandatory && spaces > 0 && extra > 0.0 => {
+                (0.0, extra / spaces as f32)
+            }
+            TextAlign::Justify if rtl => (extra, 0.0),
+            TextAlign::Justify => (0.0, 0.0),
+        };
+
+        // The tallest span of the line gives its height. Empty lines take
+        // the span they start in.
+        let mut line_spans: Vec<usize> = line_glyphs.iter().map(|g| g.span).collect();
+        if line_spans.is_empty() {
+            line_spans.push(source.span_at(line.text.start));
+        }
+        let [ascent, descent, line_gap] = line_spans.iter().fold([0.0; 3], |max, &span| {
+            let metrics = span_metrics[span];
+            [0, 1, 2].map(|k| f32::max(max[k], metrics[k]))
+        });
+
+        let line_height = (ascent + descent + line_gap) * options.line_height;
+        let baseline = top + ascent;
+        let first = layout_glyphs.len();
+
+        // Trailing spaces are left out of the reordering and stay at the
+        // end of the line in the direction of its paragraph, past `x` or
+        // the width of the line.
+        let stretch = |g: &ShapedGlyph| if g.whitespace { space_extra } else { 0.0 };
+        let levels: Vec<Level> = content.iter().map(|g| g.level).collect();
+        let content_glyphs = BidiInfo::reorder_visual(&levels)
+            .into_iter()
+            .map(|j| (&content[j], stretch(&content[j])));
+        let trailing_glyphs = line_glyphs[content.len()..].iter().map(|g| (g, 0.0));
+
+        let mut pen = x;
+        let mut visual = Vec::with_capacity(line_glyphs.len());
+        if rtl {
+            pen -= advance_sum(&line_glyphs[content.len()..]);
+            visual.extend(trailing_glyphs.rev());
+            visual.extend(content_glyphs);
+        } else {
+            visual.extend(content_glyphs);
+            visual.extend(trailing_glyphs);
+        }
+
+        for (glyph, extra) in visual {
+            let index = layout_glyphs.len();
+            let width = glyph.advance + extra;
+
+            match runs.last_mut() {
+                Some(run) if run.line == i && run.span == glyph.span => {
+                    run.glyphs.end = index + 1;
+                    run.width += width;
+                }
+                _ => runs.push(LayoutRun {
+                    span: glyph.span,
+                    line: i,
+                    glyphs: index..index + 1,
+                    x: pen,
+                    width,
+                }),
+            }
+
+            layout_glyphs.push(LayoutGlyph {
+                span: glyph.span,
+                font: glyph.font,
+                id: glyph.id,
+                cluster: glyph.cluster,
+                x: pen + glyph.offset.0,
+                y: baseline + glyph.offset.1,
+                advance: glyph.advance,
+                rtl: glyph.level.is_rtl(),
+                object: glyph.object,
+            });
+
+            pen += width;
+        }
+
+        lines.push(LayoutLine {
+            glyphs: first..layout_glyphs.len(),
+            text: line.text.clone(),
+            x,
+            top,
+            baseline,
+            width: line_width + space_extra * spaces as f32,
+            height: line_height,
+        });
+
+        top += line_height;
+    }
+
+    TextLayout {
+        glyphs: layout_glyphs,
+        width: lines.iter().map(|l| l.width).fold(0.0, f32::max),
+        height: top,
+        lines,
+        runs,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::Font;
+
+    const SIZE: f32 = 20.0;
+
+    pub(super) fn roboto() -> Font<'static> {
+        Font::from_data(include_bytes!("../../examples/common/Roboto-Regular.ttf"))
+    }
+
+    pub(super) fn layout(font: &Font<'static>, text: &str, options: LayoutOptions) -> TextLayout {
+        TextLayout::new(FontStack::from(font), text, SIZE, &options)
+    }
+
+    fn width(font: &Font<'static>, text: &str) -> f32 {
+        layout(font, text, LayoutOptions::default()).width
+    }
+
+    fn line_texts<'t>(text: &'t str, layout: &TextLayout) -> Vec<&'t str> {
+        layout
+            .lines
+            .iter()
+            .map(|line| &text[line.text.clone()])
+            .collect()
+    }
+
+    #[test]
+    fn wraps_at_word_boundaries() {
+        let font = roboto();
+        let text = "hello world foo";
+        let options = LayoutOptions {
+            max_width: Some(width(&font, "hello world") + 1.0),
+            ..LayoutOptions::default()
+        };
+
+        let layout = layout(&font, text, options);
+        assert_eq!(line_texts(text, &layout), ["hello world ", "foo"]);
+        assert_eq!(layout.lines[1].top, layout.lines[0].height);
+        assert_eq!(layout.height, layout.lines[0].height * 2.0);
+    }
+
+    #[test]
+    fn breaks_at_newlines_and_keeps_empty_lines() {
+        let font = roboto();
+        let text = "a\n\nb";
+
+        let layout = layout(&font, text, LayoutOptions::default());
+        assert_eq!(layout.lines.len(), 3);
+        assert!(layout.lines[1].glyphs.is_empty());
+        assert_eq!(layout.glyphs.len(), 2);
+    }
+
+    #[test]
+    fn drops_lines_past_max_lines() {
+        let font = roboto();
+        let options = LayoutOptions {
+            max_lines: Some(2),
+            ..LayoutOptions::default()
+        };
+
+        let layout = layout(&font, "a\nb\nc", options);
+        assert_eq!(layout.lines.len(), 2);
+        assert_eq!(layout.glyphs.len(), 2);
+    }
+
+    #[test]
+    fn lays_out_nothing_without_spans() {
+        let layout = TextLayout::from_spans("", &[], &LayoutOptions::default());
+        assert!(layout.glyphs.is_empty() && layout.lines.is_empty());
+        assert_eq!((layout.width, layout.height), (0.0, 0.0));
+        assert_eq!(layout.index_at("", 10.0, 10.0), 0);
+    }
+
+    #[test]
+    fn aligns_lines_within_max_width() {
+        let font = roboto();
+        let natural = width(&font, "hello");
+        let align = |align| {
+            let options = LayoutOptions {
+                max_width: Some(200.0),
+                align,
+                ..LayoutOptions::default()
+            };
+            layout(&font, "hello", options).lines[0].x
+        };
+
+        assert_eq!(align(TextAlign::Left), 0.0);
+        assert!((align(TextAlign::Center) - (200.0 - natural) / 2.0).abs() < 1e-3);
+        assert!((align(TextAlign::Right) - (200.0 - natural)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn aligns_to_the_start_of_the_paragraph_direction() {
+        let font = roboto();
+        let align = |text, align| {
+            let options = LayoutOptions {
+                max_width: Some(200.0),
+                align,
+                ..LayoutOptions::default()
+            };
+            let line = layout(&font, text, options).lines[0].clone();
+            (line.x, 200.0 - line.width)
+        };
+
+        // "אב cd", a right-to-left paragraph.
+        let rtl = "\u{5d0}\u{5d1} cd";
+        let (x, extra) = align(rtl, TextAlign::Start);
+        assert!((x - extra).abs() < 1e-3 && extra > 0.0);
+        assert_eq!(align(rtl, TextAlign::End).0, 0.0);
+        assert_eq!(align(rtl, TextAlign::Left).0, 0.0);
+
+        let (x, extra) = align("hello", TextAlign::End);
+        assert!((x - extra).abs() < 1e-3 && extra > 0.0);
+        assert_eq!(align("hello", TextAlign::Start).0, 0.0);
+    }
+
+    #[test]
+    fn justifies_all_lines_but_the_last() {
+        let font = roboto();
+        let max_width = width(&font, "aaa bbb ccc") + 1.0;
+        let options = LayoutOptions {
+            max_width: Some(max_width),
+            align: TextAlign::Justify,
+            ..LayoutOptions::default()
+        };
+
+        let text = "aaa bb ccc dd eee";
+        let layout = layout(&font, text, options);
+        let (last, wrapped) = layout.lines.split_last().unwrap();
+        assert!(!wrapped.is_empty());
+        for line in wrapped {
+            assert!((line.width - max_width).abs() < 1e-3);
+        }
+        assert!(last.width < max_width);
+
+        // The stretched spaces push the last word of a line to its end.
+        let end = layout.glyphs[layout.lines[0].glyphs.clone()]
+            .iter()
+            .filter(|g| text[g.cluster..].starts_with(|c: char| !c.is_whitespace()))
+            .map(|g| g.x + g.advance)
+            .fold(0.0, f32::max);
+        assert!((end - max_width).abs() < 1e-3);
+    }
+
+    #[test]
+    fn truncates_the_end_with_an_ellipsis() {
+        let font = roboto();
+        let text = "the quick brown fox jumps over the lazy dog";
+        let max_width = width(&font, "the quick brown");
+        let options = LayoutOptions {
+            max_width: Some(max_width),
+            truncate: Truncate::End,
+            ..LayoutOptions::default()
+        };
+
+        let layout = layout(&font, text, options);
+        let ellipsis = font.rusttype().glyph('\u{2026}').id();
+        assert_eq!(layout.lines.len(), 1);
+        assert!(layout.width <= max_width);
+        assert_eq!(layout.glyphs.last().unwrap().id, ellipsis);
+        assert_eq!(layout.glyphs[0].cluster, 0);
+    }
+
+    #[test]
+    fn truncates_the_end_of_the_last_allowed_line() {
+        let font = roboto();
+        let text = "one two three four five six seven";
+        let options = LayoutOptions {
+            max_width: Some(width(&font, "one two") + 1.0),
+            max_lines: Some(2),
+            truncate: Truncate::End,
+            ..LayoutOptions::default()
+        };
+
+        let layout = layout(&font, text, options);
+        let ellipsis = font.rusttype().glyph('\u{2026}').id();
+        assert_eq!(layout.lines.len(), 2);
+        assert_eq!(line_texts(text, &layout)[0], "one two ");
+        assert_eq!(layout.glyphs.last().unwrap().id, ellipsis);
+    }
+
+    #[test]
+    fn leaves_fitting_text_alone_when_truncating() {
+        let font = roboto();
+        let options = LayoutOptions {
+            max_width: Some(500.0),
+            truncate: Truncate::End,
+            ..LayoutOptions::default()
+        };
+
+        let layout = layout(&font, "short", options);
+        assert_eq!(layout.glyphs.len(), 5);
+    }
+
+    #[test]
+    fn truncates_the_middle_keeping_both_ends() {
+        let font = roboto();
+        let text = "a_very_long_file_name_indeed.txt";
+        let max_width = width(&font, text) / 2.0;
+        let options = LayoutOptions {
+            max_width: Some(max_width),
+            truncate: Truncate::Middle,
+            ..LayoutOptions::default()
+        };
+
+        let layout = layout(&font, text, options);
+        let ellipsis = font.rusttype().glyph('\u{2026}').id();
+        let glyphs = &layout.glyphs;
+        assert_eq!(layout.lines.len(), 1);
+        assert!(layout.width <= max_width);
+        assert_eq!(glyphs[0].cluster, 0);
+        assert_eq!(glyphs.last().unwrap().cluster, text.len() - 1);
+
+        let middle = glyphs.iter().position(|g| g.id == ellipsis).unwrap();
+        assert!(0 < middle && middle < glyphs.len() - 1);
+    }
+
+    #[test]
+    fn reorders_right_to_left_runs() {
+        let font = roboto();
+        // "abc אבג def", with Hebrew letters from byte 4 to byte 10.
+        let text = "abc \u{5d0}\u{5d1}\u{5d2} def";
+
+        let layout = layout(&font, text, LayoutOptions::default());
+        let clusters: Vec<usize> = layout.glyphs.iter().map(|g| g.cluster).collect();
+        assert_eq!(clusters, [0, 1, 2, 3, 8, 6, 4, 10, 11, 12, 13]);
+        assert!(layout.glyphs[4..7].iter().all(|g| g.rtl));
+        assert!(!layout.glyphs[0].rtl);
+
+        // Glyphs are placed left to right in visual order.
+        for pair in layout.glyphs.windows(2) {
+            assert!(pair[0].x <= pair[1].x);
+        }
+    }
+
+    #[test]
+    fn lays_out_right_to_left_paragraphs_from_the_right() {
+        let font = roboto();
+        // "אב cd", a right-to-left paragraph with a left-to-right run.
+        let text = "\u{5d0}\u{5d1} cd";
+
+        let layout = layout(&font, text, LayoutOptions::default());
+        let clusters: Vec<usize> = layout.glyphs.iter().map(|g| g.cluster).collect();
+        assert_eq!(clusters, [5, 6, 4, 2, 0]);
+    }
+}
@@ -1,6 +1,14 @@
+pub mod animated;
+pub mod atlas;
 pub mod drawer;
 pub mod font;
+pub mod image_data;
+pub mod image_fit;
+pub mod layout;
 pub mod rect;
+#[cfg(feature = "svg")]
+pub mod svg;
+pub mod video;
 
 pub mod opengl;
 
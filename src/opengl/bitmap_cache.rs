@@ -0,0 +1,96 @@
+use rusttype::{point, GlyphId, Scale};
+
+use super::glyph_atlas::{AtlasFormat, AtlasGlyph, GlyphAtlas, GlyphBitmap};
+
+/// A glyph at a size and a horizontal offset within a pixel, in device
+/// pixels.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Key {
+    font: usize,
+    id: GlyphId,
+    size: u32,
+    offset: u32,
+}
+
+/// A texture atlas of glyph coverage, rasterized for each size glyphs are
+/// drawn at.
+pub struct BitmapCache {
+    atlas: GlyphAtlas<Key>,
+}
+
+impl BitmapCache {
+    pub fn new() -> Self {
+        Self {
+            atlas: GlyphAtlas::new(AtlasFormat::Red, gl::NEAREST),
+        }
+    }
+
+    /// Make sure a glyph is in the atlas, and get it unless it has no
+    /// outline.
+    ///
+    /// `size` is in device pixels and `offset` is the position of the glyph
+    /// origin within a pixel. Bounds are relative to the pixel containing
+    /// the glyph origin, in device pixels.
+    pub fn get(
+        &mut self,
+        font: usize,
+        id: GlyphId,
+        rusttype_font: &rusttype::Font,
+        size: f32,
+        offset: f32,
+    ) -> Option<AtlasGlyph> {
+        let key = Key {
+            font,
+            id,
+            size: size.to_bits(),
+            offset: offset.to_bits(),
+        };
+
+        if !self.atlas.contains(&key) {
+            self.atlas
+                .insert(key, rasterize(rusttype_font, id, size, offset));
+        }
+
+        self.atlas.get(&key)
+    }
+
+    pub fn set_memory_limit(&mut self, bytes: usize) {
+        self.atlas.set_memory_limit(bytes);
+    }
+
+    pub fn clear(&mut self) {
+        self.atlas.clear();
+    }
+
+    pub fn end_frame(&mut self) {
+        self.atlas.end_frame();
+    }
+
+    pub fn bind(&self, page: usize) {
+        self.atlas.bind(page);
+    }
+}
+
+/// Rasterize the coverage of a glyph.
+///
+/// Returns `None` if the glyph has no outline.
+fn rasterize(font: &rusttype::Font, id: GlyphId, size: f32, offset: f32) -> Option<GlyphBitmap> {
+    let glyph = font
+        .glyph(id)
+        .scaled(Scale::uniform(size))
+        .positioned(point(offset, 0.0));
+    let bb = glyph.pixel_bounding_box()?;
+
+    let width = bb.width() as u32;
+    let mut pixels = vec![0; (width * bb.height() as u32) as usize];
+    glyph.draw(|x, y, v| {
+        pixels[(y * width + x) as usize] = (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+    });
+
+    Some(GlyphBitmap {
+        width,
+        height: bb.height() as u32,
+        offset: (bb.min.x as f32, bb.min.y as f32),
+        pixels,
+    })
+}
@@ -0,0 +1,457 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+use gl::types::{GLenum, GLint, GLuint};
+
+use crate::atlas::ShelfPacker;
+use crate::rect::Rect;
+
+/// Width and height of atlas pages, unless the GPU only supports smaller
+/// textures.
+const PAGE_SIZE: u32 = 1024;
+
+/// Memory the pages of each atlas may take, unless set otherwise.
+pub const DEFAULT_MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Frames after which glyphs without pixels are forgotten if not used.
+const EMPTY_GLYPH_FRAMES: u64 = 60;
+
+/// Pixels of a glyph rendered by the caller.
+pub struct GlyphBitmap {
+    pub width: u32,
+    pub height: u32,
+    /// Offset of the top-left corner of the bitmap from the glyph origin.
+    pub offset: (f32, f32),
+    /// Tightly packed rows, in the pixel format of the atlas.
+    pub pixels: Vec<u8>,
+}
+
+/// A glyph stored in a page.
+struct Entry {
+    page: usize,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    offset: (f32, f32),
+}
+
+/// What the atlas knows of a glyph.
+enum Slot {
+    Stored(Entry),
+    /// The glyph has no pixels, like a space, or is bigger than the
+    /// largest texture.
+    Empty,
+}
+
+struct Glyph {
+    slot: Slot,
+    /// Frame in which the glyph was last inserted or looked up.
+    last_used: u64,
+}
+
+/// Where to find a glyph in the atlas.
+pub struct AtlasGlyph {
+    pub page: usize,
+    pub uv: Rect,
+    /// Bounds of the bitmap relative to the glyph origin, in pixels.
+    pub bounds: Rect,
+}
+
+/// The pixel format of an atlas texture.
+#[derive(Clone, Copy)]
+pub enum AtlasFormat {
+    /// One byte per pixel.
+    Red,
+    /// Four bytes per pixel.
+    Rgba,
+}
+
+impl AtlasFormat {
+    fn gl_formats(self) -> (GLenum, GLenum) {
+        match self {
+            AtlasFormat::Red => (gl::R8, gl::RED),
+            AtlasFormat::Rgba => (gl::RGBA8, gl::RGBA),
+        }
+    }
+
+    fn bytes_per_pixel(self) -> usize {
+        match self {
+            AtlasFormat::Red => 1,
+            AtlasFormat::Rgba => 4,
+        }
+    }
+}
+
+struct Page {
+    tex_id: GLuint,
+    width: u32,
+    height: u32,
+    packer: ShelfPacker,
+    /// Frame in which a glyph of the page was last used.
+    last_used: u64,
+    /// Whether the page was added past the memory limit, to be freed at the
+    /// end of the frame.
+    overflow: bool,
+}
+
+/// Textures holding glyph bitmaps rendered on the CPU.
+///
+/// Pages are added as needed until the memory limit is reached. From then
+/// on, the least recently used pages are freed to make room, provided they
+/// were not used in the current frame. If all of them were, a page is
+/// added past the limit until the frame ends. Glyphs bigger than a page
+/// get a page of their own size.
+pub struct GlyphAtlas<K> {
+    format: AtlasFormat,
+    filter: GLenum,
+    page_size: u32,
+    /// Largest texture the GPU supports.
+    max_size: u32,
+    memory_limit: usize,
+    /// Freed pages leave a hole until a page is added, so that the other
+    /// pages keep their index.
+    pages: Vec<Option<Page>>,
+    glyphs: HashMap<K, Glyph>,
+    frame: u64,
+}
+
+impl<K: Eq + Hash> GlyphAtlas<K> {
+    /// Create an atlas, sampled with a `gl::LINEAR` or `gl::NEAREST` filter.
+    pub fn new(format: AtlasFormat, filter: GLenum) -> Self {
+        let mut max_size: GLint = 0;
+        unsafe { gl::GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_size) };
+
+        Self {
+            format,
+            filter,
+            page_size: PAGE_SIZE.min(max_size as u32),
+            max_size: max_size as u32,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            pages: Vec::new(),
+            glyphs: HashMap::new(),
+            frame: 0,
+        }
+    }
+
+    /// Limit the memory taken by pages, keeping at least one page.
+    ///
+    /// The least recently used pages past the limit are freed along with
+    /// their glyphs.
+    pub fn set_memory_limit(&mut self, bytes: usize) {
+        self.memory_limit = bytes;
+
+        while self.memory() > self.memory_limit && self.pages.iter().flatten().count() > 1 {
+            let index = self.least_recently_used(u64::MAX).unwrap();
+            self.free_page(index);
+        }
+    }
+
+    /// Free all pages and forget all glyphs.
+    pub fn clear(&mut self) {
+        for page in self.pages.drain(..).flatten() {
+            unsafe { gl::DeleteTextures(1, &page.tex_id) };
+        }
+        self.glyphs.clear();
+    }
+
+    /// Whether a glyph was inserted before. Its page then counts as used
+    /// in the current frame.
+    pub fn contains(&mut self, key: &K) -> bool {
+        let Some(glyph) = self.glyphs.get_mut(key) else {
+            return false;
+        };
+
+        match &glyph.slot {
+            Slot::Stored(entry) => {
+                if let Some(page) = &mut self.pages[entry.page] {
+                    page.last_used = self.frame;
+                }
+            }
+            Slot::Empty => {}
+        }
+
+        glyph.last_used = self.frame;
+        true
+    }
+
+    /// Store a glyph, or remember that it has no pixels.
+    ///
+    /// A glyph bigger than the largest texture is stored as having no
+    /// pixels.
+    pub fn insert(&mut self, key: K, bitmap: Option<GlyphBitmap>) {
+        let slot = match bitmap {
+            Some(bitmap) if bitmap.width.max(bitmap.height) >= self.max_size => Slot::Empty,
+            Some(bitmap) => {
+                let (page, x, y) = self.allocate(bitmap.width + 1, bitmap.height + 1);
+                self.upload(page, x, y, &bitmap);
+                Slot::Stored(Entry {
+                    page,
+                    x,
+                    y,
+                    width: bitmap.width,
+                    height: bitmap.height,
+                    offset: bitmap.offset,
+                })
+            }
+            None => Slot::Empty,
+        };
+
+        self.glyphs.insert(
+            key,
+            Glyph {
+                slot,
+                last_used: self.frame,
+            },
+        );
+    }
+
+    /// Get a glyph inserted before, unless it has no pixels.
+    pub fn get(&self, key: &K) -> Option<AtlasGlyph> {
+        let Slot::Stored(entry) = &self.glyphs.get(key)?.slot else {
+            return None;
+        };
+        let page = self.page(entry.page);
+        let (width, height) = (page.width as f32, page.height as f32);
+
+        Some(AtlasGlyph {
+            page: entry.page,
+            uv: Rect::new(
+                entry.x as f32 / width,
+                entry.y as f32 / height,
+                entry.width as f32 / width,
+                entry.height as f32 / height,
+            ),
+            bounds: Rect::new(
+                entry.offset.0,
+                entry.offset.1,
+                entry.width as f32,
+                entry.height as f32,
+            ),
+        })
+    }
+
+    /// Start counting uses for the next frame, freeing the pages added past
+    /// the memory limit.
+    ///
+    /// Glyphs without pixels that were not used for a while are forgotten
+    /// from time to time, as no page eviction ever drops them.
+    pub fn end_frame(&mut self) {
+        let overflow: Vec<usize> = (0..self.pages.len())
+            .filter(|&i| self.pages[i].as_ref().is_some_and(|page| page.overflow))
+            .collect();
+        for index in overflow.into_iter().rev() {
+            self.free_page(index);
+        }
+
+        self.frame += 1;
+
+        if self.frame.is_multiple_of(EMPTY_GLYPH_FRAMES) {
+            let frame = self.frame;
+            self.glyphs.retain(|_, glyph| match glyph.slot {
+                Slot::Stored(_) => true,
+                Slot::Empty => glyph.last_used + EMPTY_GLYPH_FRAMES >= frame,
+            });
+        }
+    }
+
+    pub fn bind(&self, page: usize) {
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, self.page(page).tex_id);
+        }
+    }
+
+    fn page(&self, index: usize) -> &Page {
+        self.pages[index].as_ref().unwrap()
+    }
+
+    fn page_bytes(&self, width: u32, height: u32) -> usize {
+        width as usize * height as usize * self.format.bytes_per_pixel()
+    }
+
+    /// Memory taken by all pages.
+    fn memory(&self) -> usize {
+        self.pages
+            .iter()
+            .flatten()
+            .map(|page| self.page_bytes(page.width, page.height))
+            .sum()
+    }
+
+    /// Find room for a glyph, returning its page and position.
+    fn allocate(&mut self, width: u32, height: u32) -> (usize, u32, u32) {
+        let found = self.pages.iter_mut().enumerate().find_map(|(i, page)| {
+            let (x, y) = page.as_mut()?.packer.allocate(width, height)?;
+            Some((i, x, y))
+        });
+
+        let (index, x, y) = match found {
+            Some(found) => found,
+            None => {
+                let index = if width > self.page_size || height > self.page_size {
+                    self.add_page(width, height)
+                } else {
+                    self.add_page(self.page_size, self.page_size)
+                };
+                let (x, y) = self.pages[index]
+                    .as_mut()
+                    .unwrap()
+                    .packer
+                    .allocate(width, height)
+                    .expect("the glyph fits in an empty page");
+                (index, x, y)
+            }
+        };
+
+        self.pages[index].as_mut().unwrap().last_used = self.frame;
+        (index, x, y)
+    }
+
+    /// Add a page, freeing the least recently used pages to stay within the
+    /// memory limit. If pages used in the current frame are in the way, the
+    /// page is an overflow page instead.
+    fn add_page(&mut self, width: u32, height: u32) -> usize {
+        let bytes = self.page_bytes(width, height);
+
+        while self.memory() + bytes > self.memory_limit {
+            let Some(index) = self.least_recently_used(self.frame) else {
+                break;
+            };
+            self.free_page(index);
+        }
+        let memory = self.memory();
+
+        let mut tex_id: GLuint = 0;
+        unsafe { gl::GenTextures(1, &mut tex_id) };
+
+        let page = Page {
+            tex_id,
+            width,
+            height,
+            packer: ShelfPacker::new(width, height),
+            last_used: self.frame,
+            overflow: memory > 0 && memory + bytes > self.memory_limit,
+        };
+        let index = match self.pages.iter().position(Option::is_none) {
+            Some(index) => {
+                self.pages[index] = Some(page);
+                index
+            }
+            None => {
+                self.pages.push(Some(page));
+                self.pages.len() - 1
+            }
+        };
+
+        self.clear_page(index);
+        index
+    }
+
+    /// The least recently used page among those last used before a frame.
+    fn least_recently_used(&self, before: u64) -> Option<usize> {
+        self.pages
+            .iter()
+            .enumerate()
+            .filter_map(|(i, page)| Some((i, page.as_ref()?)))
+            .filter(|(_, page)| page.last_used < before)
+            .min_by_key(|(_, page)| page.last_used)
+            .map(|(i, _)| i)
+    }
+
+    /// Delete the texture of a page and forget its glyphs.
+    fn free_page(&mut self, index: usize) {
+        if let Some(page) = self.pages[index].take() {
+            unsafe { gl::DeleteTextures(1, &page.tex_id) };
+        }
+        while let Some(None) = self.pages.last() {
+            self.pages.pop();
+        }
+
+        self.glyphs.retain(|_, glyph| match &glyph.slot {
+            Slot::Stored(entry) => entry.page != index,
+            _ => true,
+        });
+    }
+
+    /// Fill a page with transparent pixels, so that glyphs sampled with a
+    /// linear filter don't pick up what was there before.
+    fn clear_page(&self, index: usize) {
+        let (internal_format, format) = self.format.gl_formats();
+        let page = self.page(index);
+        let pixels = vec![0u8; self.page_bytes(page.width, page.height)];
+
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, page.tex_id);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                internal_format as i32,
+                page.width as i32,
+                page.height as i32,
+                0,
+                format,
+                gl::UNSIGNED_BYTE,
+                pixels.as_ptr() as *const _,
+            );
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, self.filter as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, self.filter as GLint);
+        }
+    }
+
+    fn upload(&self, page: usize, x: u32, y: u32, bitmap: &GlyphBitmap) {
+        let (_, format) = self.format.gl_formats();
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.page(page).tex_id);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                x as i32,
+                y as i32,
+                bitmap.width as i32,
+                bitmap.height as i32,
+                format,
+                gl::UNSIGNED_BYTE,
+                bitmap.pixels.as_ptr() as *const _,
+            );
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        }
+    }
+}
+
+impl<K> Drop for GlyphAtlas<K> {
+    fn drop(&mut self) {
+        for page in self.pages.iter().flatten() {
+            unsafe { gl::DeleteTextures(1, &page.tex_id) };
+        }
+    }
+}
+
+/// Vertices of glyph quads, grouped by the atlas page they sample.
+pub type PageBatch = BTreeMap<usize, Vec<f32>>;
+
+/// A range of vertices drawn with one atlas page bound.
+pub struct PageDraw {
+    pub page: usize,
+    pub first: i32,
+    pub count: i32,
+}
+
+/// Put batches of vertices with `components` values each into one array,
+/// along with the draws that go through them in order.
+pub fn merge_batches(batches: Vec<PageBatch>, components: usize) -> (Vec<f32>, Vec<PageDraw>) {
+    let mut data = Vec::new();
+    let mut draws = Vec::new();
+
+    for (page, mut vertices) in batches.into_iter().flatten() {
+        draws.push(PageDraw {
+            page,
+            first: (data.len() / components) as i32,
+            count: (vertices.len() / components) as i32,
+        });
+        data.append(&mut vertices);
+    }
+
+    (data, draws)
+}
@@ -1,28 +1,86 @@
 use gl::types::GLint;
 use glam::{Mat4, Vec2};
 
-use crate::drawer::ImageSource;
 use crate::rect::Rect;
 
 use super::array_buffer::ArrayBuffer;
 use super::shader::{self, AttribLocation, ShaderCompileError, ShaderProgram, UniformLocation};
+use super::texture::Texture;
+use super::video_texture::VideoTexture;
 
-pub struct ImageRenderer {
+/// A shader program drawing a textured quad, with the locations shared
+/// by all image shaders.
+struct QuadProgram {
     program: ShaderProgram,
-    buf: ArrayBuffer,
     loc_vertex: AttribLocation,
     loc_tex_coord: AttribLocation,
     loc_mvp: UniformLocation,
     loc_pos: UniformLocation,
     loc_size: UniformLocation,
+    loc_tex_pos: UniformLocation,
+    loc_tex_size: UniformLocation,
+}
+
+impl QuadProgram {
+    fn new(frag: &str) -> Result<Self, ShaderCompileError> {
+        let program = unsafe { shader::compile(IMAGE_VERT, frag) }?;
+
+        Ok(Self {
+            program,
+            loc_vertex: program.get_attrib_location("vertex").unwrap(),
+            loc_tex_coord: program.get_attrib_location("tex_coord").unwrap(),
+            loc_mvp: program.get_uniform_location("mvp").unwrap(),
+            loc_pos: program.get_uniform_location("pos").unwrap(),
+            loc_size: program.get_uniform_location("size").unwrap(),
+            loc_tex_pos: program.get_uniform_location("tex_pos").unwrap(),
+            loc_tex_size: program.get_uniform_location("tex_size").unwrap(),
+        })
+    }
+
+    /// Use the program and set the uniforms placing the quad on screen.
+    fn prepare(&self, buf: &ArrayBuffer, viewport: Vec2, rect: &Rect, uv: &Rect) {
+        let matrix = Mat4::orthographic_rh(0.0, viewport.x, viewport.y, 0.0, -1.0, 1.0);
+
+        self.program.use_program();
+        buf.bind(self.loc_vertex, 0, 2);
+        buf.bind(self.loc_tex_coord, 2, 2);
+
+        unsafe {
+            gl::Uniform2f(self.loc_pos.0, rect.x, rect.y);
+            gl::Uniform2f(self.loc_size.0, rect.w, rect.h);
+            gl::Uniform2f(self.loc_tex_pos.0, uv.x, uv.y);
+            gl::Uniform2f(self.loc_tex_size.0, uv.w, uv.h);
+            gl::UniformMatrix4fv(self.loc_mvp.0, 1, gl::FALSE, matrix.as_ref().as_ptr());
+        }
+    }
+}
+
+pub struct ImageRenderer {
+    buf: ArrayBuffer,
+    /// Quads of a batch, in screen and texture coordinates.
+    batch_buf: ArrayBuffer,
+    rgba: QuadProgram,
+    yuv: QuadProgram,
+    yuv_nv12: UniformLocation,
+    yuv_matrix: UniformLocation,
+    yuv_offset: UniformLocation,
 }
 
 const IMAGE_VERT: &str = include_str!("shaders/image.vert");
 const IMAGE_FRAG: &str = include_str!("shaders/image.frag");
+const YUV_FRAG: &str = include_str!("shaders/yuv.frag");
 
 impl ImageRenderer {
     pub fn new() -> Result<Self, ShaderCompileError> {
-        let program = unsafe { shader::compile(IMAGE_VERT, IMAGE_FRAG) }?;
+        let rgba = QuadProgram::new(IMAGE_FRAG)?;
+        let yuv = QuadProgram::new(YUV_FRAG)?;
+
+        // The chroma planes are sampled from the texture units following the luma plane.
+        yuv.program.use_program();
+        for (i, name) in ["texture1", "texture2"].into_iter().enumerate() {
+            let loc = yuv.program.get_uniform_location(name).unwrap();
+            unsafe { gl::Uniform1i(loc.0, i as i32 + 1) };
+        }
 
         let mut buf = ArrayBuffer::new(4);
         buf.set_data(vec![
@@ -31,33 +89,67 @@ impl ImageRenderer {
         ]);
 
         Ok(Self {
-            program,
             buf,
-            loc_vertex: program.get_attrib_location("vertex").unwrap(),
-            loc_tex_coord: program.get_attrib_location("tex_coord").unwrap(),
-            loc_mvp: program.get_uniform_location("mvp").unwrap(),
-            loc_pos: program.get_uniform_location("pos").unwrap(),
-            loc_size: program.get_uniform_location("size").unwrap(),
+            batch_buf: ArrayBuffer::new(4),
+            yuv_nv12: yuv.program.get_uniform_location("nv12").unwrap(),
+            yuv_matrix: yuv.program.get_uniform_location("yuv_matrix").unwrap(),
+            yuv_offset: yuv.program.get_uniform_location("yuv_offset").unwrap(),
+            rgba,
+            yuv,
         })
     }
 
-    pub fn draw(&self, viewport: Vec2, rect: &Rect, image: &ImageSource) {
-        let matrix = Mat4::orthographic_rh(0.0, viewport.x, viewport.y, 0.0, -1.0, 1.0);
+    /// Add the vertices of a quad drawing the part of a texture inside
+    /// `uv`, given in texture coordinates, to a batch.
+    pub fn quad(vertices: &mut Vec<f32>, rect: &Rect, uv: &Rect) {
+        for (x, y) in [(0., 0.), (1., 0.), (1., 1.), (0., 0.), (1., 1.), (0., 1.)] {
+            vertices.extend([
+                rect.x + x * rect.w,
+                rect.y + y * rect.h,
+                uv.x + x * uv.w,
+                uv.y + y * uv.h,
+            ]);
+        }
+    }
 
-        image.bind();
+    /// Draw a batch of quads sampling the same texture, with one draw call.
+    pub fn draw(&mut self, viewport: Vec2, vertices: Vec<f32>, texture: &Texture) {
+        let unit = Rect::new(0., 0., 1., 1.);
 
-        self.program.use_program();
-        self.buf.bind(self.loc_vertex, 0, 2);
-        self.buf.bind(self.loc_tex_coord, 2, 2);
+        self.batch_buf.set_data(vertices);
+        texture.bind();
+        self.rgba.prepare(&self.batch_buf, viewport, &unit, &unit);
 
         unsafe {
             gl::ActiveTexture(gl::TEXTURE0);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
 
-            gl::Uniform2f(self.loc_pos.0, rect.x, rect.y);
-            gl::Uniform2f(self.loc_size.0, rect.w, rect.h);
-            gl::UniformMatrix4fv(self.loc_mvp.0, 1, gl::FALSE, matrix.as_ref().as_ptr());
+            gl::Enable(gl::BLEND);
+            if texture.premultiplied() {
+                gl::BlendFunc(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+            } else {
+                gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            }
+            gl::DrawArrays(gl::TRIANGLES, 0, self.batch_buf.len() as i32);
+        }
+    }
+
+    /// Draw the part of a video frame inside `uv`, given in texture coordinates.
+    pub fn draw_video(&self, viewport: Vec2, rect: &Rect, uv: &Rect, video: &VideoTexture) {
+        video.bind();
+        self.yuv.prepare(&self.buf, viewport, rect, uv);
+
+        unsafe {
+            gl::Uniform1f(self.yuv_nv12.0, if video.nv12() { 1.0 } else { 0.0 });
+            gl::UniformMatrix3fv(
+                self.yuv_matrix.0,
+                1,
+                gl::FALSE,
+                video.matrix().as_ref().as_ptr(),
+            );
+            gl::Uniform3fv(self.yuv_offset.0, 1, video.offset().as_ref().as_ptr());
+
             gl::Enable(gl::BLEND);
             gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
             gl::DrawArrays(gl::TRIANGLES, 0, self.buf.len() as i32);
@@ -1,20 +1,47 @@
+use std::collections::HashMap;
+
 use glam::{vec2, Vec2};
 
-use crate::drawer::{Drawer, ImageSource, RectBlueprint, TextBlueprint};
+use crate::drawer::{Drawer, DroppedImages, ImageId, RectBlueprint, RichTextBlueprint, SubImage};
+use crate::image_data::ImageData;
+use crate::layout::TextLayout;
 use crate::rect::Rect;
+use crate::video::VideoFrame;
 
 use self::image_renderer::ImageRenderer;
 use self::rect_renderer::RectRenderer;
+pub use self::subpixel_cache::SubpixelOrder;
 use self::text_renderer::TextRenderer;
+pub use self::text_renderer::{GlyphQuality, TextRenderMode};
+use self::texture::Texture;
+use self::video_texture::VideoTexture;
 
 mod array_buffer;
+mod bitmap_cache;
+mod glyph_atlas;
+mod sdf_cache;
 mod shader;
+mod subpixel_cache;
 mod texture;
+mod video_texture;
 
 mod image_renderer;
 mod rect_renderer;
 mod text_renderer;
 
+/// The OpenGL resources behind an [`ImageId`].
+enum GlImage {
+    Texture(Texture),
+    Video(VideoTexture),
+}
+
+/// Quads sampling the same texture, drawn together before anything else
+/// is drawn.
+struct ImageBatch {
+    image: ImageId,
+    vertices: Vec<f32>,
+}
+
 pub struct OpenglDrawer {
     pub dpi: f32,
     pub rect: Rect,
@@ -23,6 +50,10 @@ pub struct OpenglDrawer {
     rect_renderer: RectRenderer,
     text_renderer: TextRenderer,
     image_renderer: ImageRenderer,
+    images: HashMap<ImageId, GlImage>,
+    next_image_id: u32,
+    image_batch: Option<ImageBatch>,
+    dropped_images: DroppedImages,
 }
 
 impl OpenglDrawer {
@@ -37,6 +68,141 @@ impl OpenglDrawer {
             text_renderer: TextRenderer::new(dpi).unwrap(),
             image_renderer: ImageRenderer::new().unwrap(),
             alpha: 1.0,
+            images: HashMap::new(),
+            next_image_id: 0,
+            image_batch: None,
+            dropped_images: DroppedImages::new(),
+        }
+    }
+
+    /// Choose how text is rasterized from now on.
+    pub fn set_text_render_mode(&mut self, mode: TextRenderMode) {
+        self.text_renderer.set_mode(mode);
+    }
+
+    /// Choose how finely glyphs are cached from now on.
+    pub fn set_glyph_quality(&mut self, quality: GlyphQuality) {
+        self.text_renderer.set_quality(quality);
+    }
+
+    /// Limit the memory taken by the textures of each glyph cache, 16 MiB
+    /// by default. Glyphs drawn least recently are evicted to stay within
+    /// the limit.
+    pub fn set_glyph_cache_limit(&mut self, bytes: usize) {
+        self.text_renderer.set_cache_limit(bytes);
+    }
+
+    fn insert_image(&mut self, image: GlImage) -> ImageId {
+        let id = ImageId::from_raw(self.next_image_id);
+        self.next_image_id += 1;
+
+        self.images.insert(id, image);
+        id
+    }
+
+    /// Draw the images queued so far.
+    ///
+    /// Images are drawn in batches, so that images packed into the same
+    /// atlas take a single draw call. Drawing anything else and ending the
+    /// frame draws pending images first; call this before drawing with
+    /// OpenGL directly.
+    pub fn flush_images(&mut self) {
+        let Some(batch) = self.image_batch.take() else {
+            return;
+        };
+
+        if let Some(GlImage::Texture(texture)) = self.images.get(&batch.image) {
+            self.image_renderer
+                .draw(self.viewport, batch.vertices, texture);
+        }
+    }
+
+    fn draw_texture(&mut self, rect: &Rect, uv: &Rect, image: ImageId) {
+        match self.images.get(&image) {
+            Some(GlImage::Texture(_)) => {
+                if self
+                    .image_batch
+                    .as_ref()
+                    .is_some_and(|batch| batch.image != image)
+                {
+                    self.flush_images();
+                }
+
+                let batch = self.image_batch.get_or_insert_with(|| ImageBatch {
+                    image,
+                    vertices: Vec::new(),
+                });
+                ImageRenderer::quad(&mut batch.vertices, rect, uv);
+            }
+            Some(GlImage::Video(_)) => {
+                self.flush_images();
+                if let Some(GlImage::Video(video)) = self.images.get(&image) {
+                    self.image_renderer
+                        .draw_video(self.viewport, rect, uv, video);
+                }
+            }
+            None => (),
+        }
+    }
+
+    /// Draw the images standing for spans of a text.
+    fn draw_inline_images(&mut self, spec: &RichTextBlueprint, layout: &TextLayout) {
+        for glyph in layout.glyphs.iter().filter(|glyph| glyph.object) {
+            let Some(image) = spec.spans[glyph.span].style.image else {
+                continue;
+            };
+
+            let rect = Rect::new(
+                spec.x + glyph.x,
+                spec.y + glyph.y - image.baseline,
+                image.width,
+                image.height,
+            );
+            self.draw_texture(&rect, &image.image.uv(), image.image.image());
+        }
+    }
+
+    /// Draw the backgrounds or the underlines and strikethroughs of the
+    /// spans of a text.
+    fn draw_span_rects(
+        &mut self,
+        spec: &RichTextBlueprint,
+        layout: &TextLayout,
+        backgrounds: bool,
+    ) {
+        for run in &layout.runs {
+            let style = &spec.spans[run.span].style;
+            let line = &layout.lines[run.line];
+            let font = style.font.primary();
+
+            let mut rects = Vec::new();
+            if backgrounds {
+                rects.extend(style.background.map(|col| (line.top, line.height, col)));
+            } else {
+                if style.underline {
+                    let (offset, thickness) = font.underline_metrics(style.size);
+                    rects.push((line.baseline + offset, thickness, style.col));
+                }
+                if style.strikethrough {
+                    let (offset, thickness) = font.strikeout_metrics(style.size);
+                    rects.push((line.baseline + offset, thickness, style.col));
+                }
+            }
+
+            for (y, h, color) in rects {
+                self.rect_renderer.draw(
+                    self.viewport,
+                    &RectBlueprint {
+                        rect: Rect::new(spec.x + run.x, spec.y + y, run.width, h),
+                        color,
+                        border_color: 0,
+                        border_width: 0.,
+                        corner_radius: 0.,
+                        borders: [false; 4],
+                        alpha: style.alpha,
+                    },
+                );
+            }
         }
     }
 }
@@ -44,6 +210,7 @@ impl OpenglDrawer {
 impl Drawer for OpenglDrawer {
     #[doc(hidden)]
     fn resize(&mut self, viewport: Vec2, dpi: f32) {
+        self.flush_images();
         self.viewport = viewport;
         self.rect.w = viewport.x;
         self.rect.h = viewport.y;
@@ -54,30 +221,78 @@ impl Drawer for OpenglDrawer {
         }
     }
 
-    fn begin_frame(&mut self) {
-        self.text_renderer.begin_frame();
-    }
+    fn begin_frame(&mut self) {}
 
     fn end_frame(&mut self) {
+        self.flush_images();
         self.text_renderer.end_frame();
+
+        for image in self.dropped_images.take() {
+            self.images.remove(&image);
+        }
     }
 
     fn clear(&mut self) {
+        self.flush_images();
         unsafe {
             gl::ClearColor(0., 0., 0., 0.);
             gl::Clear(gl::COLOR_BUFFER_BIT);
         }
     }
 
+    fn create_image(&mut self, data: &ImageData) -> ImageId {
+        self.insert_image(GlImage::Texture(Texture::from_data(data)))
+    }
+
+    fn update_image(&mut self, image: ImageId, x: u32, y: u32, data: &ImageData) {
+        self.flush_images();
+        if let Some(GlImage::Texture(texture)) = self.images.get(&image) {
+            texture.update(x, y, data);
+        }
+    }
+
+    fn create_video_image(&mut self, frame: &VideoFrame) -> ImageId {
+        self.insert_image(GlImage::Video(VideoTexture::from_frame(frame)))
+    }
+
+    fn update_video_image(&mut self, image: ImageId, frame: &VideoFrame) {
+        self.flush_images();
+        if let Some(GlImage::Video(video)) = self.images.get_mut(&image) {
+            video.update(frame);
+        }
+    }
+
+    fn destroy_image(&mut self, image: ImageId) {
+        self.flush_images();
+        self.images.remove(&image);
+    }
+
+    fn dropped_images(&self) -> Option<DroppedImages> {
+        Some(self.dropped_images.clone())
+    }
+
     fn draw_rect(&mut self, spec: &RectBlueprint) {
+        self.flush_images();
         self.rect_renderer.draw(self.viewport, spec);
     }
 
-    fn draw_text(&mut self, spec: &TextBlueprint) {
-        self.text_renderer.draw(self.viewport, self.dpi, spec);
+    fn draw_rich_text(&mut self, spec: &RichTextBlueprint) {
+        let layout = spec.layout();
+
+        self.flush_images();
+        self.draw_span_rects(spec, &layout, true);
+        self.text_renderer
+            .draw(self.viewport, self.dpi, spec, &layout);
+        self.draw_inline_images(spec, &layout);
+        self.flush_images();
+        self.draw_span_rects(spec, &layout, false);
+    }
+
+    fn draw_image(&mut self, rect: &Rect, image: &SubImage) {
+        self.draw_texture(rect, &image.uv(), image.image());
     }
 
-    fn draw_image(&mut self, rect: &Rect, image: &ImageSource) {
-        self.image_renderer.draw(self.viewport, rect, image);
+    fn draw_image_region(&mut self, rect: &Rect, src: &Rect, image: &SubImage) {
+        self.draw_texture(rect, &image.region_uv(src), image.image());
     }
 }
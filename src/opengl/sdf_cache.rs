@@ -0,0 +1,179 @@
+use rusttype::{point, GlyphId, Scale};
+
+use super::glyph_atlas::{AtlasFormat, AtlasGlyph, GlyphAtlas, GlyphBitmap};
+
+/// Size in pixels at which glyphs are rasterized into distance fields.
+pub const SDF_SIZE: f32 = 48.0;
+
+/// Distance in pixels covered by a distance field on each side of the
+/// outline of a glyph.
+pub const SDF_SPREAD: u32 = 8;
+
+/// A texture atlas of glyphs rasterized once as signed distance fields,
+/// which can then be drawn smoothly at any size.
+pub struct SdfCache {
+    /// Glyphs by font cache id and glyph id.
+    atlas: GlyphAtlas<(usize, GlyphId)>,
+}
+
+impl SdfCache {
+    pub fn new() -> Self {
+        Self {
+            atlas: GlyphAtlas::new(AtlasFormat::Red, gl::LINEAR),
+        }
+    }
+
+    /// Make sure a glyph is in the atlas.
+    pub fn queue(&mut self, font: usize, id: GlyphId, rusttype_font: &rusttype::Font) {
+        if !self.atlas.contains(&(font, id)) {
+            self.atlas.insert((font, id), rasterize(rusttype_font, id));
+        }
+    }
+
+    /// Get a glyph queued before, unless it has no outline.
+    ///
+    /// Bounds are in pixels at [`SDF_SIZE`].
+    pub fn get(&self, font: usize, id: GlyphId) -> Option<AtlasGlyph> {
+        self.atlas.get(&(font, id))
+    }
+
+    pub fn set_memory_limit(&mut self, bytes: usize) {
+        self.atlas.set_memory_limit(bytes);
+    }
+
+    pub fn end_frame(&mut self) {
+        self.atlas.end_frame();
+    }
+
+    pub fn bind(&self, page: usize) {
+        self.atlas.bind(page);
+    }
+}
+
+/// Rasterize a glyph at [`SDF_SIZE`] and turn it into a distance field.
+///
+/// Returns `None` if the glyph has no outline.
+fn rasterize(font: &rusttype::Font, id: GlyphId) -> Option<GlyphBitmap> {
+    let glyph = font
+        .glyph(id)
+        .scaled(Scale::uniform(SDF_SIZE))
+        .positioned(point(0.0, 0.0));
+    let bb = glyph.pixel_bounding_box()?;
+
+    let width = bb.width() as u32 + 2 * SDF_SPREAD;
+    let height = bb.height() as u32 + 2 * SDF_SPREAD;
+    let mut coverage = vec![0.0; (width * height) as usize];
+    glyph.draw(|x, y, v| {
+        coverage[((y + SDF_SPREAD) * width + x + SDF_SPREAD) as usize] = v;
+    });
+
+    let offset = (
+        (bb.min.x - SDF_SPREAD as i32) as f32,
+        (bb.min.y - SDF_SPREAD as i32) as f32,
+    );
+
+    Some(GlyphBitmap {
+        width,
+        height,
+        offset,
+        pixels: distance_field(&coverage, width, height),
+    })
+}
+
+/// Compute a signed distance field from the coverage of a glyph.
+///
+/// Values are `128` on the outline, growing inside the glyph and reaching
+/// `0` at [`SDF_SPREAD`] pixels outside of it. Pixels partly covered by
+/// the glyph start at a distance from the outline given by their
+/// coverage, which keeps edges smooth rather than following pixels.
+fn distance_field(coverage: &[f32], width: u32, height: u32) -> Vec<u8> {
+    // Coverage is rounded to bytes first, as rasterizers leave tiny values
+    // all around glyphs.
+    let seed = |c: f32, far: f64| match (c * 255.0).round() / 255.0 {
+        c if c >= 1.0 => 0.0,
+        c if c <= 0.0 => far,
+        c => (0.5 - c as f64).max(0.0).powi(2),
+    };
+
+    let to_inside: Vec<f64> = coverage.iter().map(|&c| seed(c, FAR)).collect();
+    let to_outside: Vec<f64> = coverage.iter().map(|&c| seed(1.0 - c, FAR)).collect();
+    let to_inside = squared_distances(to_inside, width, height);
+    let to_outside = squared_distances(to_outside, width, height);
+
+    (0..coverage.len())
+        .map(|i| {
+            let distance = to_outside[i].sqrt() - to_inside[i].sqrt();
+
+            let value = 0.5 + distance as f32 / (2 * SDF_SPREAD) as f32;
+            (value.clamp(0.0, 1.0) * 255.0).round() as u8
+        })
+        .collect()
+}
+
+/// Squared distance used for pixels with nothing nearby.
+const FAR: f64 = 1e20;
+
+/// Squared distance from each pixel to the closest pixel, given how far
+/// each pixel is by itself, using the separable transform of Felzenszwalb
+/// and Huttenlocher.
+fn squared_distances(mut grid: Vec<f64>, width: u32, height: u32) -> Vec<f64> {
+    let (width, height) = (width as usize, height as usize);
+
+    let n = width.max(height);
+    let mut f = vec![0.0; n];
+    let mut d = vec![0.0; n];
+    let mut v = vec![0; n];
+    let mut z = vec![0.0; n + 1];
+
+    for x in 0..width {
+        for y in 0..height {
+            f[y] = grid[y * width + x];
+        }
+        distances_1d(&f[..height], &mut d, &mut v, &mut z);
+        for y in 0..height {
+            grid[y * width + x] = d[y];
+        }
+    }
+
+    for y in 0..height {
+        let row = &mut grid[y * width..(y + 1) * width];
+        f[..width].copy_from_slice(row);
+        distances_1d(&f[..width], &mut d, &mut v, &mut z);
+        row.copy_from_slice(&d[..width]);
+    }
+
+    grid
+}
+
+/// One-dimensional squared distance transform of a sampled function.
+fn distances_1d(f: &[f64], d: &mut [f64], v: &mut [usize], z: &mut [f64]) {
+    let parabola = |q: usize| f[q] + (q * q) as f64;
+    let intersection = |q: usize, p: usize| (parabola(q) - parabola(p)) / (2 * q - 2 * p) as f64;
+
+    let mut k = 0;
+    v[0] = 0;
+    z[0] = f64::NEG_INFINITY;
+    z[1] = f64::INFINITY;
+
+    for q in 1..f.len() {
+        let mut s = intersection(q, v[k]);
+        while s <= z[k] {
+            k -= 1;
+            s = intersection(q, v[k]);
+        }
+
+        k += 1;
+        v[k] = q;
+        z[k] = s;
+        z[k + 1] = f64::INFINITY;
+    }
+
+    k = 0;
+    for (q, distance) in d.iter_mut().enumerate().take(f.len()) {
+        while z[k + 1] < q as f64 {
+            k += 1;
+        }
+        let offset = q as f64 - v[k] as f64;
+        *distance = offset * offset + f[v[k]];
+    }
+}
@@ -101,7 +101,7 @@ unsafe fn verify_shader(shader: GLuint) -> Result<(), ShaderCompileError> {
             gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
             if len > 0 {
                 let mut log = String::with_capacity(len as usize);
-                log.extend(std::iter::repeat('\0').take(len as usize));
+                log.extend(std::iter::repeat_n('\0', len as usize));
                 gl::GetShaderInfoLog(shader, len, &mut len, log[..].as_ptr() as *mut GLchar);
                 log.truncate(len as usize);
                 log
@@ -126,7 +126,7 @@ unsafe fn verify_program(program: GLuint) -> Result<(), ShaderCompileError> {
             gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
             if len > 0 {
                 let mut log = String::with_capacity(len as usize);
-                log.extend(std::iter::repeat('\0').take(len as usize));
+                log.extend(std::iter::repeat_n('\0', len as usize));
                 gl::GetProgramInfoLog(program, len, &mut len, log[..].as_ptr() as *mut GLchar);
                 log.truncate(len as usize);
                 log
@@ -0,0 +1,13 @@
+#version 100
+
+precision mediump float;
+
+uniform sampler2D texture0;
+
+varying vec2 fragment_tex_coord;
+varying vec4 fragment_col;
+
+void main() {
+  vec4 tex_data = texture2D(texture0, fragment_tex_coord);
+  gl_FragColor = vec4(tex_data.rgb, tex_data.a * fragment_col.a);
+}
\ No newline at end of file
@@ -7,10 +7,11 @@ attribute vec2 tex_coord;
 
 uniform mat4 mvp;
 uniform vec2 pos, size;
+uniform vec2 tex_pos, tex_size;
 
 varying vec2 fragment_tex_coord;
 
 void main() {
   gl_Position = mvp * vec4(pos + vertex * size, 0.0, 1.0);
-  fragment_tex_coord = tex_coord;
+  fragment_tex_coord = tex_pos + tex_coord * tex_size;
 }
\ No newline at end of file
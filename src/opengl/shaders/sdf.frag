@@ -0,0 +1,16 @@
+#version 100
+
+precision mediump float;
+
+uniform sampler2D texture0;
+
+varying vec2 fragment_tex_coord;
+varying vec4 fragment_col;
+varying float fragment_edge;
+varying float fragment_smoothing;
+
+void main() {
+  float distance = texture2D(texture0, fragment_tex_coord).r;
+  float alpha = smoothstep(fragment_edge - fragment_smoothing, fragment_edge + fragment_smoothing, distance);
+  gl_FragColor = vec4(fragment_col.rgb, fragment_col.a * alpha);
+}
\ No newline at end of file
@@ -0,0 +1,24 @@
+#version 100
+
+precision mediump float;
+
+attribute vec2 vertex;
+attribute vec2 tex_coord;
+attribute vec4 col;
+attribute float edge;
+attribute float smoothing;
+
+uniform mat4 mvp;
+
+varying vec2 fragment_tex_coord;
+varying vec4 fragment_col;
+varying float fragment_edge;
+varying float fragment_smoothing;
+
+void main() {
+  gl_Position = mvp * vec4(vertex, 0.0, 1.0);
+  fragment_tex_coord = tex_coord;
+  fragment_col = col;
+  fragment_edge = edge;
+  fragment_smoothing = smoothing;
+}
\ No newline at end of file
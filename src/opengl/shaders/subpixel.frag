@@ -0,0 +1,22 @@
+#version 100
+
+precision mediump float;
+
+uniform sampler2D texture0;
+
+// 0 to darken the destination by the coverage, 1 to add the color.
+uniform float pass;
+
+varying vec2 fragment_tex_coord;
+varying vec4 fragment_col;
+
+void main() {
+  vec3 coverage = texture2D(texture0, fragment_tex_coord).rgb * fragment_col.a;
+  float alpha = max(coverage.r, max(coverage.g, coverage.b));
+
+  if (pass < 0.5) {
+    gl_FragColor = vec4(coverage, alpha);
+  } else {
+    gl_FragColor = vec4(fragment_col.rgb * coverage, alpha);
+  }
+}
\ No newline at end of file
@@ -2,12 +2,12 @@
 
 precision mediump float;
 
-uniform vec4 col;
 uniform sampler2D texture0;
 
 varying vec2 fragment_tex_coord;
+varying vec4 fragment_col;
 
 void main() {
   vec4 tex_data = texture2D(texture0, fragment_tex_coord);
-  gl_FragColor = vec4(col.rgb, col.a * tex_data.r);
+  gl_FragColor = vec4(fragment_col.rgb, fragment_col.a * tex_data.r);
 }
\ No newline at end of file
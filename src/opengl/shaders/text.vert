@@ -4,12 +4,15 @@ precision mediump float;
 
 attribute vec2 vertex;
 attribute vec2 tex_coord;
+attribute vec4 col;
 
 uniform mat4 mvp;
 
 varying vec2 fragment_tex_coord;
+varying vec4 fragment_col;
 
 void main() {
   gl_Position = mvp * vec4(vertex, 0.0, 1.0);
   fragment_tex_coord = tex_coord;
+  fragment_col = col;
 }
\ No newline at end of file
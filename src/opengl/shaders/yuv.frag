@@ -0,0 +1,20 @@
+#version 100
+
+precision mediump float;
+
+uniform sampler2D texture0;
+uniform sampler2D texture1;
+uniform sampler2D texture2;
+uniform float nv12;
+uniform mat3 yuv_matrix;
+uniform vec3 yuv_offset;
+
+varying vec2 fragment_tex_coord;
+
+void main() {
+  float y = texture2D(texture0, fragment_tex_coord).r;
+  vec2 planar_uv = vec2(texture2D(texture1, fragment_tex_coord).r, texture2D(texture2, fragment_tex_coord).r);
+  vec2 uv = mix(planar_uv, texture2D(texture1, fragment_tex_coord).rg, nv12);
+  vec3 rgb = yuv_matrix * (vec3(y, uv) - yuv_offset);
+  gl_FragColor = vec4(clamp(rgb, 0.0, 1.0), 1.0);
+}
\ No newline at end of file
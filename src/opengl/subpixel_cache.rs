@@ -0,0 +1,153 @@
+use rusttype::{point, GlyphId, Scale};
+
+use super::glyph_atlas::{AtlasFormat, AtlasGlyph, GlyphAtlas, GlyphBitmap};
+
+/// Order of the color stripes making up the pixels of a display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SubpixelOrder {
+    Rgb,
+    Bgr,
+}
+
+/// Weights of the filter spreading the coverage of each subpixel over its
+/// neighbours, which reduces color fringes.
+const LCD_FILTER: [f32; 5] = [1. / 9., 2. / 9., 3. / 9., 2. / 9., 1. / 9.];
+
+/// A glyph at a size in device pixels and a horizontal position in thirds
+/// of a pixel.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Key {
+    font: usize,
+    id: GlyphId,
+    size: u32,
+    phase: u32,
+}
+
+/// A texture atlas of glyphs rasterized at three times the horizontal
+/// resolution, with the coverage of each subpixel in one color channel.
+pub struct SubpixelCache {
+    order: SubpixelOrder,
+    atlas: GlyphAtlas<Key>,
+}
+
+impl SubpixelCache {
+    pub fn new(order: SubpixelOrder) -> Self {
+        Self {
+            order,
+            atlas: GlyphAtlas::new(AtlasFormat::Rgba, gl::NEAREST),
+        }
+    }
+
+    pub fn order(&self) -> SubpixelOrder {
+        self.order
+    }
+
+    /// Make sure a glyph is in the atlas, and get it unless it has no
+    /// outline.
+    ///
+    /// `size` is in device pixels and `phase` is the position of the glyph
+    /// origin within a pixel, in thirds of a pixel. Bounds are relative to
+    /// the pixel containing the glyph origin, in device pixels.
+    pub fn get(
+        &mut self,
+        font: usize,
+        id: GlyphId,
+        rusttype_font: &rusttype::Font,
+        size: f32,
+        phase: u32,
+    ) -> Option<AtlasGlyph> {
+        let key = Key {
+            font,
+            id,
+            size: size.to_bits(),
+            phase,
+        };
+
+        if !self.atlas.contains(&key) {
+            let bitmap = rasterize(rusttype_font, id, size, phase, self.order);
+            self.atlas.insert(key, bitmap);
+        }
+
+        self.atlas.get(&key)
+    }
+
+    pub fn set_memory_limit(&mut self, bytes: usize) {
+        self.atlas.set_memory_limit(bytes);
+    }
+
+    pub fn clear(&mut self) {
+        self.atlas.clear();
+    }
+
+    pub fn end_frame(&mut self) {
+        self.atlas.end_frame();
+    }
+
+    pub fn bind(&self, page: usize) {
+        self.atlas.bind(page);
+    }
+}
+
+/// Rasterize a glyph at three times the horizontal resolution and filter
+/// the coverage into RGBA pixels.
+///
+/// Returns `None` if the glyph has no outline.
+fn rasterize(
+    font: &rusttype::Font,
+    id: GlyphId,
+    size: f32,
+    phase: u32,
+    order: SubpixelOrder,
+) -> Option<GlyphBitmap> {
+    let glyph = font
+        .glyph(id)
+        .scaled(Scale {
+            x: size * 3.0,
+            y: size,
+        })
+        .positioned(point(0.0, 0.0));
+    let bb = glyph.pixel_bounding_box()?;
+
+    // Leave a pixel on each side for the filter to spread into.
+    let first = phase as i32 + bb.min.x;
+    let left = first.div_euclid(3) - 1;
+    let pad = (first - left * 3) as usize;
+    let width = (pad + bb.width() as usize + 2).div_ceil(3) as u32;
+    let height = bb.height() as u32;
+
+    let row_len = width as usize * 3;
+    let mut coverage = vec![0.0; row_len * height as usize];
+    glyph.draw(|x, y, v| {
+        coverage[y as usize * row_len + pad + x as usize] = v;
+    });
+
+    let mut pixels = Vec::with_capacity(coverage.len() / 3 * 4);
+    for row in coverage.chunks(row_len) {
+        let filtered: Vec<f32> = (0..row_len)
+            .map(|i| {
+                LCD_FILTER
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(k, w)| Some(w * row.get((i + k).checked_sub(2)?)?))
+                    .sum()
+            })
+            .collect();
+
+        for rgb in filtered.chunks(3) {
+            let [r, g, b] = match order {
+                SubpixelOrder::Rgb => [rgb[0], rgb[1], rgb[2]],
+                SubpixelOrder::Bgr => [rgb[2], rgb[1], rgb[0]],
+            };
+
+            let max = r.max(g).max(b);
+            pixels.extend([r, g, b, max].map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8));
+        }
+    }
+
+    Some(GlyphBitmap {
+        width,
+        height,
+        offset: (left as f32, bb.min.y as f32),
+        pixels,
+    })
+}
@@ -1,213 +1,810 @@
-use gl::types::*;
+use std::collections::HashMap;
 
-use glam::{vec4, Mat4, Vec2};
-use rusttype::gpu_cache::Cache;
-use rusttype::PositionedGlyph;
+use glam::{vec2, vec4, Mat4, Vec2, Vec4};
+use rusttype::GlyphId;
 
-use crate::drawer::TextBlueprint;
+use crate::drawer::{RichTextBlueprint, TextStyle};
+use crate::font::ColorGlyphKind;
+use crate::layout::{LayoutGlyph, TextLayout};
 
 use super::array_buffer::ArrayBuffer;
+use super::bitmap_cache::BitmapCache;
+use super::glyph_atlas::{
+    merge_batches, AtlasFormat, AtlasGlyph, GlyphAtlas, GlyphBitmap, PageBatch,
+    DEFAULT_MEMORY_LIMIT,
+};
+use super::sdf_cache::{SdfCache, SDF_SIZE, SDF_SPREAD};
 use super::shader::{self, AttribLocation, ShaderCompileError, ShaderProgram, UniformLocation};
+use super::subpixel_cache::{SubpixelCache, SubpixelOrder};
+
+/// How glyphs are rasterized.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TextRenderMode {
+    /// Glyphs are rasterized for each size and position they are drawn
+    /// at, which gives the sharpest text at a fixed size.
+    #[default]
+    Bitmap,
+    /// Glyphs are rasterized once as signed distance fields and scaled
+    /// smoothly to any size, which suits animated or zoomed text.
+    Sdf,
+    /// Glyphs are rasterized at three times the horizontal resolution
+    /// and each subpixel of the display is lit separately, which gives
+    /// sharper text on low density displays with the given stripe order.
+    ///
+    /// Only suited to opaque backgrounds and unscaled output.
+    Subpixel(SubpixelOrder),
+}
+
+/// Trade-offs between the sharpness of text and the number of glyphs kept
+/// in glyph caches.
+///
+/// Glyphs are cached in device pixels, so these settings mean the same on
+/// every display.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlyphQuality {
+    /// Number of horizontal positions within a pixel glyphs are rasterized
+    /// at in [`TextRenderMode::Bitmap`]. `1` places glyphs on whole pixels,
+    /// more gives evener spacing but caches more glyphs.
+    pub subpixel_positions: u32,
+    /// Sizes glyphs are rasterized at are rounded to a multiple of this,
+    /// in device pixels, so that close sizes share glyphs. `0.0` keeps
+    /// exact sizes. Does not apply to [`TextRenderMode::Sdf`].
+    pub size_step: f32,
+}
+
+impl Default for GlyphQuality {
+    fn default() -> Self {
+        Self {
+            subpixel_positions: 4,
+            size_step: 0.0,
+        }
+    }
+}
+
+impl GlyphQuality {
+    /// Size a glyph drawn at `size` device pixels is rasterized at.
+    fn size(&self, size: f32) -> f32 {
+        match self.size_step {
+            step if step > 0.0 => ((size / step).round() * step).max(step),
+            _ => size,
+        }
+    }
+}
+
+/// Values of a distance field at which the parts of a glyph are drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FieldEdges {
+    /// Half the change of the field over one pixel on screen.
+    smoothing: f32,
+    /// Edge of the outline, or of the glyph if it has none.
+    outline: f32,
+    /// Smoothing of the shadow, widened by its blur up to the outline edge.
+    shadow: f32,
+}
+
+impl FieldEdges {
+    /// Compute the edges of a style at a scale factor, or `None` if its
+    /// text is too small to be seen.
+    fn new(style: &TextStyle, dpi: f32) -> Option<Self> {
+        if !style.size.is_finite() || style.size <= 0.0 {
+            return None;
+        }
+
+        let scale = style.size / SDF_SIZE;
+        let to_field = |px: f32| px / scale / (2 * SDF_SPREAD) as f32;
+
+        let smoothing = to_field(0.5 / dpi);
+        let outline = match style.outline {
+            Some(outline) => (0.5 - to_field(outline.width)).max(smoothing),
+            None => 0.5,
+        };
+        let shadow = style.shadow.map_or(smoothing, |shadow| {
+            to_field(shadow.blur).max(smoothing).min(outline)
+        });
+
+        Some(Self {
+            smoothing,
+            outline,
+            shadow,
+        })
+    }
+}
+
+/// The shader program and glyph atlas drawing text in [`TextRenderMode::Sdf`].
+struct SdfRenderer {
+    program: ShaderProgram,
+    buf: ArrayBuffer,
+    loc_vertex: AttribLocation,
+    loc_tex_coord: AttribLocation,
+    loc_col: AttribLocation,
+    loc_edge: AttribLocation,
+    loc_smoothing: AttribLocation,
+    loc_mvp: UniformLocation,
+    cache: SdfCache,
+}
+
+/// The shader program and glyph atlas drawing text in
+/// [`TextRenderMode::Subpixel`].
+struct SubpixelRenderer {
+    program: ShaderProgram,
+    buf: ArrayBuffer,
+    loc_vertex: AttribLocation,
+    loc_tex_coord: AttribLocation,
+    loc_col: AttribLocation,
+    loc_mvp: UniformLocation,
+    loc_pass: UniformLocation,
+    /// Created for the first subpixel order drawn with, and again when it
+    /// changes.
+    cache: Option<SubpixelCache>,
+    memory_limit: usize,
+}
+
+/// A glyph in color at a size in device pixels.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct ColorKey {
+    font: usize,
+    id: GlyphId,
+    size: u32,
+    /// The color of the text, for `COLR` glyphs with layers using it.
+    col: Option<u32>,
+}
+
+/// Number of glyph kinds remembered by the color renderer before they are
+/// forgotten all at once.
+const MAX_COLOR_KINDS: usize = 65536;
+
+/// The shader program and glyph atlas drawing glyphs of color fonts, like
+/// emoji, in every render mode.
+struct ColorRenderer {
+    program: ShaderProgram,
+    buf: ArrayBuffer,
+    loc_vertex: AttribLocation,
+    loc_tex_coord: AttribLocation,
+    loc_col: AttribLocation,
+    loc_mvp: UniformLocation,
+    atlas: GlyphAtlas<ColorKey>,
+    /// Kinds of the glyphs of color fonts by font and glyph, `None` for
+    /// glyphs without a color version.
+    kinds: HashMap<(usize, GlyphId), Option<ColorGlyphKind>>,
+}
 
 /// Render text on screen.
 pub struct TextRenderer {
+    mode: TextRenderMode,
+    quality: GlyphQuality,
+    /// Scale factor the size-dependent caches were filled at.
+    dpi: f32,
+    sdf: SdfRenderer,
+    subpixel: SubpixelRenderer,
+    color: ColorRenderer,
     program: ShaderProgram,
     buf: ArrayBuffer,
-    tex_id: u32,
     loc_vertex: AttribLocation,
     loc_tex_coord: AttribLocation,
-    loc_col: UniformLocation,
+    loc_col: AttribLocation,
     loc_mvp: UniformLocation,
-    cache: Cache<'static>,
-    used_glyphs: Vec<PositionedGlyph<'static>>,
+    cache: BitmapCache,
 }
 
 const TEXT_VERT: &str = include_str!("shaders/text.vert");
 const TEXT_FRAG: &str = include_str!("shaders/text.frag");
+const SDF_VERT: &str = include_str!("shaders/sdf.vert");
+const SDF_FRAG: &str = include_str!("shaders/sdf.frag");
+const SUBPIXEL_FRAG: &str = include_str!("shaders/subpixel.frag");
+const COLOR_FRAG: &str = include_str!("shaders/color.frag");
 
-impl TextRenderer {
-    /// Create a text renderer for a specified window size.
-    pub fn new(dpi: f32) -> Result<Self, ShaderCompileError> {
-        let cache: Cache<'static> = Cache::builder()
-            .dimensions(0, 0)
-            .scale_tolerance(dpi)
-            .position_tolerance(dpi)
-            .build();
+/// Push the vertices of a glyph from an atlas with bounds in device pixels,
+/// its origin being at `x` and `y` in device pixels.
+fn quad(data: &mut Vec<f32>, glyph: &AtlasGlyph, x: f32, y: f32, dpi: f32, col: Vec4) {
+    let x0 = (x + glyph.bounds.x) / dpi;
+    let y0 = (y + glyph.bounds.y) / dpi;
+    let (x1, y1) = (x0 + glyph.bounds.w / dpi, y0 + glyph.bounds.h / dpi);
+    let (u0, v0) = (glyph.uv.x, glyph.uv.y);
+    let (u1, v1) = (u0 + glyph.uv.w, v0 + glyph.uv.h);
 
-        let mut tex_id: GLuint = 0;
-        unsafe { gl::GenTextures(1, &mut tex_id) };
+    let corners = [
+        (x0, y0, u0, v0),
+        (x1, y0, u1, v0),
+        (x1, y1, u1, v1),
+        (x0, y0, u0, v0),
+        (x1, y1, u1, v1),
+        (x0, y1, u0, v1),
+    ];
 
-        let program = unsafe { shader::compile(TEXT_VERT, TEXT_FRAG) }?;
+    for (x, y, u, v) in corners {
+        data.extend([x, y, u, v]);
+        data.extend(col.to_array());
+    }
+}
+
+fn color(col: u32, alpha: f32) -> Vec4 {
+    vec4(
+        ((col & 0xff0000) >> 16) as f32 / 255.0,
+        ((col & 0x00ff00) >> 8) as f32 / 255.0,
+        (col & 0x0000ff) as f32 / 255.0,
+        alpha,
+    )
+}
+
+impl SdfRenderer {
+    fn new() -> Result<Self, ShaderCompileError> {
+        let program = unsafe { shader::compile(SDF_VERT, SDF_FRAG) }?;
 
-        let mut slf = Self {
+        Ok(Self {
             loc_vertex: program.get_attrib_location("vertex").unwrap(),
             loc_tex_coord: program.get_attrib_location("tex_coord").unwrap(),
-            loc_col: program.get_uniform_location("col").unwrap(),
+            loc_col: program.get_attrib_location("col").unwrap(),
+            loc_edge: program.get_attrib_location("edge").unwrap(),
+            loc_smoothing: program.get_attrib_location("smoothing").unwrap(),
             loc_mvp: program.get_uniform_location("mvp").unwrap(),
-            buf: ArrayBuffer::new(4),
+            buf: ArrayBuffer::new(10),
             program,
-            tex_id,
-            cache,
-            used_glyphs: vec![],
-        };
-
-        slf.set_cache_size(1);
-        Ok(slf)
+            cache: SdfCache::new(),
+        })
     }
 
-    fn set_cache_size(&mut self, size: u32) {
-        self.cache
-            .to_builder()
-            .dimensions(size, size)
-            .rebuild(&mut self.cache);
+    fn draw(&mut self, viewport: Vec2, dpi: f32, spec: &RichTextBlueprint, glyphs: &[LayoutGlyph]) {
+        for glyph in glyphs {
+            let font = spec.spans[glyph.span].style.font.get(glyph.font);
+            self.cache.queue(font.cache_id(), glyph.id, font.rusttype());
+        }
 
-        unsafe {
-            gl::BindTexture(gl::TEXTURE_2D, self.tex_id);
-            gl::ActiveTexture(gl::TEXTURE0);
-            gl::TexImage2D(
-                gl::TEXTURE_2D, // target
-                0,              // level
-                gl::R8 as i32,
-                size as i32, // width
-                size as i32, // height
-                0,           // border, must be 0
-                gl::RED,
-                gl::UNSIGNED_BYTE,
-                std::ptr::null(),
+        // Shadows, outlines and fills are drawn in separate passes, so that
+        // an outline never covers a neighbouring glyph.
+        let mut shadows = PageBatch::new();
+        let mut outlines = PageBatch::new();
+        let mut fills = PageBatch::new();
+
+        for glyph in glyphs {
+            let style = &spec.spans[glyph.span].style;
+            let font = style.font.get(glyph.font);
+            let Some(sdf) = self.cache.get(font.cache_id(), glyph.id) else {
+                continue;
+            };
+            let Some(edges) = FieldEdges::new(style, dpi) else {
+                continue;
+            };
+
+            let scale = style.size / SDF_SIZE;
+            let FieldEdges {
+                smoothing,
+                outline: edge,
+                ..
+            } = edges;
+
+            let origin = vec2(spec.x + glyph.x, spec.y + glyph.y);
+            let quad = |data: &mut Vec<f32>, offset: Vec2, col: Vec4, edge: f32, smoothing: f32| {
+                let p0 = origin + offset + vec2(sdf.bounds.x, sdf.bounds.y) * scale;
+                let p1 = p0 + vec2(sdf.bounds.w, sdf.bounds.h) * scale;
+                let (u0, v0) = (sdf.uv.x, sdf.uv.y);
+                let (u1, v1) = (u0 + sdf.uv.w, v0 + sdf.uv.h);
+
+                let corners = [
+                    (p0.x, p0.y, u0, v0),
+                    (p1.x, p0.y, u1, v0),
+                    (p1.x, p1.y, u1, v1),
+                    (p0.x, p0.y, u0, v0),
+                    (p1.x, p1.y, u1, v1),
+                    (p0.x, p1.y, u0, v1),
+                ];
+
+                for (x, y, u, v) in corners {
+                    data.extend([x, y, u, v]);
+                    data.extend(col.to_array());
+                    data.extend([edge, smoothing]);
+                }
+            };
+
+            if let Some(shadow) = style.shadow {
+                let col = color(shadow.col, shadow.alpha * style.alpha);
+                quad(
+                    shadows.entry(sdf.page).or_default(),
+                    shadow.offset,
+                    col,
+                    edge,
+                    edges.shadow,
+                );
+            }
+
+            if let Some(outline) = style.outline {
+                let col = color(outline.col, outline.alpha * style.alpha);
+                quad(
+                    outlines.entry(sdf.page).or_default(),
+                    Vec2::ZERO,
+                    col,
+                    edge,
+                    smoothing,
+                );
+            }
+
+            let col = color(style.col, style.alpha);
+            quad(
+                fills.entry(sdf.page).or_default(),
+                Vec2::ZERO,
+                col,
+                0.5,
+                smoothing,
             );
         }
-    }
 
-    fn render_cache(&mut self) {
+        let (data, draws) = merge_batches(vec![shadows, outlines, fills], 10);
+        self.buf.set_data(data);
+
+        let m = Mat4::orthographic_rh(0.0, viewport.x, viewport.y, 0.0, -1.0, 1.0);
+        self.program.use_program();
+        self.buf.bind(self.loc_vertex, 0, 2);
+        self.buf.bind(self.loc_tex_coord, 2, 2);
+        self.buf.bind(self.loc_col, 4, 4);
+        self.buf.bind(self.loc_edge, 8, 1);
+        self.buf.bind(self.loc_smoothing, 9, 1);
+
         unsafe {
-            gl::BindTexture(gl::TEXTURE_2D, self.tex_id);
-        }
-
-        let mut cache_misses = 0;
-        let mut do_build = true;
-        while do_build {
-            let res = self.cache.cache_queued(|rect, data| {
-                unsafe {
-                    gl::ActiveTexture(gl::TEXTURE0);
-                    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
-                    gl::TexSubImage2D(
-                        gl::TEXTURE_2D,
-                        0,
-                        rect.min.x as i32,
-                        rect.min.y as i32,
-                        (rect.width()) as i32,
-                        rect.height() as i32,
-                        gl::RED,
-                        gl::UNSIGNED_BYTE,
-                        data.as_ptr() as *const _,
-                    );
-                }
+            gl::UniformMatrix4fv(self.loc_mvp.0, 1, gl::FALSE, m.as_ref().as_ptr());
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        }
 
-                cache_misses += 1;
-            });
+        for draw in draws {
+            self.cache.bind(draw.page);
+            unsafe { gl::DrawArrays(gl::TRIANGLES, draw.first, draw.count) };
+        }
+    }
+}
 
-            match res {
-                Err(_) => {
-                    cache_misses = 0;
-                    self.set_cache_size(self.cache.dimensions().0 * 2);
-                }
-                Ok(_) => {
-                    do_build = false;
-                }
+impl SubpixelRenderer {
+    fn new() -> Result<Self, ShaderCompileError> {
+        let program = unsafe { shader::compile(TEXT_VERT, SUBPIXEL_FRAG) }?;
+
+        Ok(Self {
+            loc_vertex: program.get_attrib_location("vertex").unwrap(),
+            loc_tex_coord: program.get_attrib_location("tex_coord").unwrap(),
+            loc_col: program.get_attrib_location("col").unwrap(),
+            loc_mvp: program.get_uniform_location("mvp").unwrap(),
+            loc_pass: program.get_uniform_location("pass").unwrap(),
+            buf: ArrayBuffer::new(8),
+            program,
+            cache: None,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+        })
+    }
+
+    fn draw(
+        &mut self,
+        viewport: Vec2,
+        dpi: f32,
+        order: SubpixelOrder,
+        spec: &RichTextBlueprint,
+        sizes: &[f32],
+        glyphs: &[LayoutGlyph],
+    ) {
+        let cache = match &mut self.cache {
+            Some(cache) if cache.order() == order => cache,
+            cache => {
+                let cache = cache.insert(SubpixelCache::new(order));
+                cache.set_memory_limit(self.memory_limit);
+                cache
+            }
+        };
+
+        let mut batch = PageBatch::new();
+        for glyph in glyphs {
+            let style = &spec.spans[glyph.span].style;
+            let font = style.font.get(glyph.font);
+
+            // Glyphs are placed on whole pixels vertically and on thirds of
+            // a pixel horizontally.
+            let x = ((spec.x + glyph.x) * dpi * 3.0).round() as i32;
+            let y = ((spec.y + glyph.y) * dpi).round();
+            let phase = x.rem_euclid(3) as u32;
+
+            let size = sizes[glyph.span];
+            let Some(bitmap) = cache.get(font.cache_id(), glyph.id, font.rusttype(), size, phase)
+            else {
+                continue;
             };
+
+            let col = color(style.col, style.alpha);
+            let x = x.div_euclid(3) as f32;
+            quad(
+                batch.entry(bitmap.page).or_default(),
+                &bitmap,
+                x,
+                y,
+                dpi,
+                col,
+            );
         }
+
+        let (data, draws) = merge_batches(vec![batch], 8);
+        self.buf.set_data(data);
+
+        let m = Mat4::orthographic_rh(0.0, viewport.x, viewport.y, 0.0, -1.0, 1.0);
+        self.program.use_program();
+        self.buf.bind(self.loc_vertex, 0, 2);
+        self.buf.bind(self.loc_tex_coord, 2, 2);
+        self.buf.bind(self.loc_col, 4, 4);
+
+        // Blending with a separate alpha per color channel takes two
+        // passes without dual-source blending.
+        unsafe {
+            gl::UniformMatrix4fv(self.loc_mvp.0, 1, gl::FALSE, m.as_ref().as_ptr());
+            gl::Enable(gl::BLEND);
+        }
+
+        for draw in draws {
+            cache.bind(draw.page);
+            unsafe {
+                gl::Uniform1f(self.loc_pass.0, 0.0);
+                gl::BlendFunc(gl::ZERO, gl::ONE_MINUS_SRC_COLOR);
+                gl::DrawArrays(gl::TRIANGLES, draw.first, draw.count);
+
+                gl::Uniform1f(self.loc_pass.0, 1.0);
+                gl::BlendFunc(gl::ONE, gl::ONE);
+                gl::DrawArrays(gl::TRIANGLES, draw.first, draw.count);
+            }
+        }
+
+        unsafe { gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA) };
+    }
+}
+
+impl ColorRenderer {
+    fn new() -> Result<Self, ShaderCompileError> {
+        let program = unsafe { shader::compile(TEXT_VERT, COLOR_FRAG) }?;
+
+        Ok(Self {
+            loc_vertex: program.get_attrib_location("vertex").unwrap(),
+            loc_tex_coord: program.get_attrib_location("tex_coord").unwrap(),
+            loc_col: program.get_attrib_location("col").unwrap(),
+            loc_mvp: program.get_uniform_location("mvp").unwrap(),
+            buf: ArrayBuffer::new(8),
+            program,
+            atlas: GlyphAtlas::new(AtlasFormat::Rgba, gl::NEAREST),
+            kinds: HashMap::new(),
+        })
+    }
+
+    /// Key of a glyph in the atlas, `None` if it has no color version.
+    fn key(
+        &mut self,
+        spec: &RichTextBlueprint,
+        sizes: &[f32],
+        glyph: &LayoutGlyph,
+    ) -> Option<ColorKey> {
+        let style = &spec.spans[glyph.span].style;
+        let font = style.font.get(glyph.font);
+        if !font.has_color_glyphs() {
+            return None;
+        }
+
+        if self.kinds.len() >= MAX_COLOR_KINDS {
+            self.kinds.clear();
+        }
+        let kind = *self
+            .kinds
+            .entry((font.cache_id(), glyph.id))
+            .or_insert_with(|| font.color_glyph_kind(glyph.id));
+
+        Some(ColorKey {
+            font: font.cache_id(),
+            id: glyph.id,
+            size: sizes[glyph.span].to_bits(),
+            col: (kind? == ColorGlyphKind::Foreground).then_some(style.col),
+        })
     }
 
-    fn append_vertices(&self, glyph: &PositionedGlyph, pr: f32, data: &mut Vec<f32>) {
-        let rect = self.cache.rect_for(0, glyph).unwrap();
-        if rect.is_none() {
+    /// Rasterize a glyph if it has a color version, and tell whether it
+    /// has one.
+    fn queue(&mut self, spec: &RichTextBlueprint, sizes: &[f32], glyph: &LayoutGlyph) -> bool {
+        let Some(key) = self.key(spec, sizes, glyph) else {
+            return false;
+        };
+
+        if !self.atlas.contains(&key) {
+            let style = &spec.spans[glyph.span].style;
+            let font = style.font.get(glyph.font);
+            let bitmap = font
+                .color_glyph(glyph.id, sizes[glyph.span], style.col)
+                .map(|glyph| GlyphBitmap {
+                    width: glyph.width,
+                    height: glyph.height,
+                    offset: glyph.offset,
+                    pixels: glyph.pixels,
+                });
+
+            self.atlas.insert(key, bitmap);
+        }
+
+        self.atlas.get(&key).is_some()
+    }
+
+    /// Draw glyphs queued before.
+    fn draw(
+        &mut self,
+        viewport: Vec2,
+        dpi: f32,
+        spec: &RichTextBlueprint,
+        sizes: &[f32],
+        glyphs: &[LayoutGlyph],
+    ) {
+        if glyphs.is_empty() {
             return;
         }
 
-        let (uv, screen) = rect.unwrap();
-        data.extend([
-            screen.min.x as f32 / pr,
-            screen.min.y as f32 / pr,
-            uv.min.x,
-            uv.min.y,
-            screen.max.x as f32 / pr,
-            screen.min.y as f32 / pr,
-            uv.max.x,
-            uv.min.y,
-            screen.max.x as f32 / pr,
-            screen.max.y as f32 / pr,
-            uv.max.x,
-            uv.max.y,
-            screen.min.x as f32 / pr,
-            screen.min.y as f32 / pr,
-            uv.min.x,
-            uv.min.y,
-            screen.max.x as f32 / pr,
-            screen.max.y as f32 / pr,
-            uv.max.x,
-            uv.max.y,
-            screen.min.x as f32 / pr,
-            screen.max.y as f32 / pr,
-            uv.min.x,
-            uv.max.y,
-        ]);
-    }
-
-    /// Draw text.
-    pub fn draw(&mut self, viewport: Vec2, dpi: f32, spec: &TextBlueprint) {
+        let mut batch = PageBatch::new();
+        for glyph in glyphs {
+            let Some(bitmap) = self
+                .key(spec, sizes, glyph)
+                .and_then(|key| self.atlas.get(&key))
+            else {
+                continue;
+            };
+
+            let style = &spec.spans[glyph.span].style;
+            let x = ((spec.x + glyph.x) * dpi).round();
+            let y = ((spec.y + glyph.y) * dpi).round();
+            let col = color(style.col, style.alpha);
+            quad(
+                batch.entry(bitmap.page).or_default(),
+                &bitmap,
+                x,
+                y,
+                dpi,
+                col,
+            );
+        }
+
+        let (data, draws) = merge_batches(vec![batch], 8);
+        self.buf.set_data(data);
+
         let m = Mat4::orthographic_rh(0.0, viewport.x, viewport.y, 0.0, -1.0, 1.0);
-        let c = vec4(
-            ((spec.col & 0xff0000) >> 16) as f32 / 255.0,
-            ((spec.col & 0x00ff00) >> 8) as f32 / 255.0,
-            (spec.col & 0x0000ff) as f32 / 255.0,
-            spec.alpha,
-        );
+        self.program.use_program();
+        self.buf.bind(self.loc_vertex, 0, 2);
+        self.buf.bind(self.loc_tex_coord, 2, 2);
+        self.buf.bind(self.loc_col, 4, 4);
+
+        unsafe {
+            gl::UniformMatrix4fv(self.loc_mvp.0, 1, gl::FALSE, m.as_ref().as_ptr());
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        }
+
+        for draw in draws {
+            self.atlas.bind(draw.page);
+            unsafe { gl::DrawArrays(gl::TRIANGLES, draw.first, draw.count) };
+        }
+    }
+}
+
+impl TextRenderer {
+    /// Create a text renderer for a display with a scale factor.
+    pub fn new(dpi: f32) -> Result<Self, ShaderCompileError> {
+        let program = unsafe { shader::compile(TEXT_VERT, TEXT_FRAG) }?;
+
+        Ok(Self {
+            mode: TextRenderMode::Bitmap,
+            quality: GlyphQuality::default(),
+            dpi,
+            sdf: SdfRenderer::new()?,
+            subpixel: SubpixelRenderer::new()?,
+            color: ColorRenderer::new()?,
+            loc_vertex: program.get_attrib_location("vertex").unwrap(),
+            loc_tex_coord: program.get_attrib_location("tex_coord").unwrap(),
+            loc_col: program.get_attrib_location("col").unwrap(),
+            loc_mvp: program.get_uniform_location("mvp").unwrap(),
+            buf: ArrayBuffer::new(8),
+            program,
+            cache: BitmapCache::new(),
+        })
+    }
 
-        let x = spec.x * dpi;
-        let y = spec.y + spec.font.baseline(spec.size) * dpi;
-        let glyphs = spec.font.create_glyphs(spec.text, x, y, spec.size * dpi);
+    pub fn set_mode(&mut self, mode: TextRenderMode) {
+        self.mode = mode;
+    }
 
-        for glyph in &glyphs {
-            self.used_glyphs.push(glyph.clone());
-            self.cache.queue_glyph(0, glyph.clone());
+    pub fn set_quality(&mut self, quality: GlyphQuality) {
+        self.quality = GlyphQuality {
+            subpixel_positions: quality.subpixel_positions.max(1),
+            ..quality
+        };
+    }
+
+    /// Free the glyphs rasterized for the previous scale factor when it
+    /// changes, e.g. when a window moves to another display. Distance
+    /// fields don't depend on it and are kept.
+    fn set_dpi(&mut self, dpi: f32) {
+        if dpi == self.dpi {
+            return;
         }
 
-        self.render_cache();
-        let mut data: Vec<f32> = vec![];
+        self.dpi = dpi;
+        self.cache.clear();
+        if let Some(cache) = &mut self.subpixel.cache {
+            cache.clear();
+        }
+        self.color.atlas.clear();
+    }
+
+    /// Draw laid out rich text.
+    pub fn draw(
+        &mut self,
+        viewport: Vec2,
+        dpi: f32,
+        spec: &RichTextBlueprint,
+        layout: &TextLayout,
+    ) {
+        self.set_dpi(dpi);
+
+        // Sizes of the spans in device pixels, as rasterized.
+        let sizes: Vec<f32> = spec
+            .spans
+            .iter()
+            .map(|span| self.quality.size(span.style.size * dpi))
+            .collect();
+
+        let (color, glyphs): (Vec<LayoutGlyph>, Vec<LayoutGlyph>) = layout
+            .glyphs
+            .iter()
+            .filter(|glyph| !glyph.object)
+            .copied()
+            .partition(|glyph| self.color.queue(spec, &sizes, glyph));
+
+        let effects = spec
+            .spans
+            .iter()
+            .any(|span| span.style.outline.is_some() || span.style.shadow.is_some());
+
+        match self.mode {
+            _ if effects => self.sdf.draw(viewport, dpi, spec, &glyphs),
+            TextRenderMode::Sdf => self.sdf.draw(viewport, dpi, spec, &glyphs),
+            TextRenderMode::Subpixel(order) => {
+                self.subpixel
+                    .draw(viewport, dpi, order, spec, &sizes, &glyphs);
+            }
+            TextRenderMode::Bitmap => self.draw_bitmap(viewport, dpi, spec, &sizes, &glyphs),
+        }
+
+        self.color.draw(viewport, dpi, spec, &sizes, &color);
+    }
+
+    fn draw_bitmap(
+        &mut self,
+        viewport: Vec2,
+        dpi: f32,
+        spec: &RichTextBlueprint,
+        sizes: &[f32],
+        glyphs: &[LayoutGlyph],
+    ) {
+        let m = Mat4::orthographic_rh(0.0, viewport.x, viewport.y, 0.0, -1.0, 1.0);
+        let positions = self.quality.subpixel_positions as f32;
+
+        let mut batch = PageBatch::new();
         for glyph in glyphs {
-            self.append_vertices(&glyph, dpi, &mut data);
+            let style = &spec.spans[glyph.span].style;
+            let font = style.font.get(glyph.font);
+
+            // Glyphs are placed on whole pixels vertically and on fractions
+            // of a pixel horizontally.
+            let x = ((spec.x + glyph.x) * dpi * positions).round() / positions;
+            let y = ((spec.y + glyph.y) * dpi).round();
+            let offset = x - x.floor();
+
+            let size = sizes[glyph.span];
+            let Some(bitmap) =
+                self.cache
+                    .get(font.cache_id(), glyph.id, font.rusttype(), size, offset)
+            else {
+                continue;
+            };
+
+            let x = x.floor();
+
+            let col = color(style.col, style.alpha);
+            quad(
+                batch.entry(bitmap.page).or_default(),
+                &bitmap,
+                x,
+                y,
+                dpi,
+                col,
+            );
         }
 
+        let (data, draws) = merge_batches(vec![batch], 8);
         self.buf.set_data(data);
 
         self.program.use_program();
         self.buf.bind(self.loc_vertex, 0, 2);
         self.buf.bind(self.loc_tex_coord, 2, 2);
+        self.buf.bind(self.loc_col, 4, 4);
 
         unsafe {
-            gl::BindTexture(gl::TEXTURE_2D, self.tex_id);
-            gl::ActiveTexture(gl::TEXTURE0);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
-
-            gl::Uniform4fv(self.loc_col.0, 1, c.as_ref().as_ptr());
             gl::UniformMatrix4fv(self.loc_mvp.0, 1, gl::FALSE, m.as_ref().as_ptr());
             gl::Enable(gl::BLEND);
             gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-            gl::DrawArrays(gl::TRIANGLES, 0, self.buf.len() as i32);
+        }
+
+        for draw in draws {
+            self.cache.bind(draw.page);
+            unsafe { gl::DrawArrays(gl::TRIANGLES, draw.first, draw.count) };
         }
     }
 
-    pub fn begin_frame(&mut self) {
-        self.used_glyphs.clear();
+    /// Limit the memory taken by the textures of each glyph atlas. Render
+    /// modes and color glyphs have atlases of their own.
+    pub fn set_cache_limit(&mut self, bytes: usize) {
+        self.sdf.cache.set_memory_limit(bytes);
+        self.subpixel.memory_limit = bytes;
+        if let Some(cache) = &mut self.subpixel.cache {
+            cache.set_memory_limit(bytes);
+        }
+        self.color.atlas.set_memory_limit(bytes);
+        self.cache.set_memory_limit(bytes);
     }
 
+    /// Let glyph atlases evict glyphs drawn before this frame.
     pub fn end_frame(&mut self) {
-        for glyph in self.used_glyphs.clone() {
-            self.cache.queue_glyph(0, glyph);
+        self.sdf.cache.end_frame();
+        if let Some(cache) = &mut self.subpixel.cache {
+            cache.end_frame();
         }
+        self.color.atlas.end_frame();
+        self.cache.end_frame();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drawer::{TextOutline, TextShadow};
+    use crate::font::{Font, FontStack};
+
+    fn edges(size: f32, outline: Option<f32>, blur: Option<f32>) -> Option<FieldEdges> {
+        let font = Font::from_data(include_bytes!("../../examples/common/Roboto-Regular.ttf"));
+        let mut style = TextStyle::new(FontStack::from(&font), size, 0);
+        style.outline = outline.map(|width| TextOutline {
+            width,
+            col: 0,
+            alpha: 1.0,
+        });
+        style.shadow = blur.map(|blur| TextShadow {
+            offset: Vec2::ZERO,
+            blur,
+            col: 0,
+            alpha: 1.0,
+        });
+
+        FieldEdges::new(&style, 1.0)
+    }
+
+    #[test]
+    fn widens_the_shadow_up_to_the_outline_edge() {
+        let sharp = edges(24.0, None, Some(0.0)).unwrap();
+        assert_eq!(sharp.shadow, sharp.smoothing);
+        assert_eq!(sharp.outline, 0.5);
+
+        let blurred = edges(24.0, Some(1.0), Some(2.0)).unwrap();
+        assert!(blurred.smoothing < blurred.shadow && blurred.shadow <= blurred.outline);
+
+        let very_blurred = edges(24.0, Some(1.0), Some(100.0)).unwrap();
+        assert_eq!(very_blurred.shadow, very_blurred.outline);
+    }
+
+    #[test]
+    fn handles_tiny_text() {
+        for size in [0.1, 1.0, 2.9] {
+            for outline in [None, Some(1.0)] {
+                let edges = edges(size, outline, Some(1.0)).unwrap();
+                assert!(edges.smoothing.is_finite() && edges.shadow.is_finite());
+                assert!(edges.shadow <= edges.outline);
+            }
+        }
+    }
 
-        self.render_cache();
-        self.used_glyphs.clear();
+    #[test]
+    fn skips_invisible_text() {
+        assert_eq!(edges(0.0, None, Some(1.0)), None);
+        assert_eq!(edges(-1.0, Some(1.0), Some(1.0)), None);
+        assert_eq!(edges(f32::NAN, None, None), None);
     }
 }
@@ -1,29 +1,144 @@
-use gl::types::GLuint;
+use std::borrow::Cow;
 
-use crate::drawer::ImageSource;
+use gl::types::{GLenum, GLuint};
 
-impl ImageSource {
-    pub fn from_memory(width: u32, height: u32, pixels_rgba: &[u8]) -> Self {
+use crate::image_data::{ImageData, PixelFormat};
+
+/// An OpenGL RGBA texture backing an image.
+pub struct Texture {
+    id: GLuint,
+    premultiplied: bool,
+}
+
+/// Pixels ready to be handed to OpenGL, with the unpack parameters
+/// needed to read them.
+struct Upload<'a> {
+    pixels: Cow<'a, [u8]>,
+    format: GLenum,
+    row_length: i32,
+    alignment: i32,
+}
+
+impl<'a> Upload<'a> {
+    fn new(data: &ImageData<'a>, premultiplied: bool) -> Self {
+        assert!(data.is_valid(), "pixel buffer too small for image");
+
+        let format = match data.format {
+            PixelFormat::Rgb8 => gl::RGB,
+            PixelFormat::Rgba8 | PixelFormat::PremultipliedRgba8 => gl::RGBA,
+            PixelFormat::Bgra8 => gl::BGRA,
+            // Luminance formats don't exist in core profiles.
+            PixelFormat::Gray8 | PixelFormat::GrayAlpha8 => {
+                return Self::converted(data, premultiplied)
+            }
+        };
+
+        if data.format.is_premultiplied() != premultiplied {
+            return Self::converted(data, premultiplied);
+        }
+
+        let bpp = data.format.bytes_per_pixel();
+        if data.stride.is_multiple_of(bpp) {
+            return Self {
+                pixels: Cow::Borrowed(data.pixels),
+                format,
+                row_length: (data.stride / bpp) as i32,
+                alignment: 1,
+            };
+        }
+
+        // Rows padded to a multiple of the unpack alignment can be read as is.
+        let row_len = data.row_len() as u32;
+        let alignment = [8, 4, 2, 1]
+            .into_iter()
+            .find(|&a| row_len.div_ceil(a) * a == data.stride);
+
+        match alignment {
+            Some(alignment) => Self {
+                pixels: Cow::Borrowed(data.pixels),
+                format,
+                row_length: 0,
+                alignment: alignment as i32,
+            },
+            None => Self::converted(data, premultiplied),
+        }
+    }
+
+    fn converted(data: &ImageData, premultiplied: bool) -> Self {
+        Self {
+            pixels: Cow::Owned(data.to_rgba(premultiplied)),
+            format: gl::RGBA,
+            row_length: 0,
+            alignment: 1,
+        }
+    }
+
+    /// Set the unpack parameters, run `f` with the pixels, then restore
+    /// the default parameters.
+    unsafe fn with_unpack(&self, f: impl FnOnce(*const u8)) {
+        gl::PixelStorei(gl::UNPACK_ROW_LENGTH, self.row_length);
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, self.alignment);
+        f(self.pixels.as_ptr());
+        gl::PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+    }
+}
+
+impl Texture {
+    /// Create a texture from pixels.
+    pub fn from_data(data: &ImageData) -> Self {
+        let premultiplied = data.format.is_premultiplied();
+        let upload = Upload::new(data, premultiplied);
         let mut id: GLuint = 0;
 
         unsafe {
             gl::GenTextures(1, &mut id);
             gl::BindTexture(gl::TEXTURE_2D, id);
 
-            gl::TexImage2D(
-                gl::TEXTURE_2D,
-                0,
-                gl::RGBA as i32,
-                width as i32,
-                height as i32,
-                0,
-                gl::RGBA,
-                gl::UNSIGNED_BYTE,
-                pixels_rgba.as_ptr() as *const _,
-            );
+            upload.with_unpack(|pixels| {
+                gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    gl::RGBA as i32,
+                    data.width as i32,
+                    data.height as i32,
+                    0,
+                    upload.format,
+                    gl::UNSIGNED_BYTE,
+                    pixels as *const _,
+                );
+            });
+        }
+
+        Self { id, premultiplied }
+    }
+
+    /// Replace the region of the texture starting at `x` and `y` with new pixels.
+    pub fn update(&self, x: u32, y: u32, data: &ImageData) {
+        let upload = Upload::new(data, self.premultiplied);
+
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+
+            upload.with_unpack(|pixels| {
+                gl::TexSubImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    x as i32,
+                    y as i32,
+                    data.width as i32,
+                    data.height as i32,
+                    upload.format,
+                    gl::UNSIGNED_BYTE,
+                    pixels as *const _,
+                );
+            });
         }
+    }
 
-        Self { id, width, height }
+    /// Whether the color components of the texture are multiplied by alpha.
+    pub fn premultiplied(&self) -> bool {
+        self.premultiplied
     }
 
     pub fn bind(&self) {
@@ -31,7 +146,7 @@ impl ImageSource {
     }
 }
 
-impl Drop for ImageSource {
+impl Drop for Texture {
     fn drop(&mut self) {
         unsafe {
             gl::DeleteTextures(1, [self.id].as_ptr());
@@ -0,0 +1,191 @@
+use std::borrow::Cow;
+
+use gl::types::{GLenum, GLint, GLuint};
+use glam::{Mat3, Vec3};
+
+use crate::video::{VideoFrame, VideoPlane, VideoPlanes};
+
+/// OpenGL textures holding the planes of a YUV video frame.
+///
+/// Each plane is stored in its own texture and converted to RGB in the
+/// fragment shader.
+pub struct VideoTexture {
+    planes: Vec<GLuint>,
+    /// Width, height and format the plane textures are allocated with.
+    layout: Vec<(u32, u32, GLenum)>,
+    nv12: bool,
+    matrix: Mat3,
+    offset: Vec3,
+}
+
+impl VideoTexture {
+    /// Create textures for the planes of a video frame.
+    pub fn from_frame(frame: &VideoFrame) -> Self {
+        let mut slf = Self {
+            planes: Vec::new(),
+            layout: Vec::new(),
+            nv12: false,
+            matrix: Mat3::IDENTITY,
+            offset: Vec3::ZERO,
+        };
+
+        slf.update(frame);
+        slf
+    }
+
+    /// Replace the contents of the textures with a new video frame.
+    pub fn update(&mut self, frame: &VideoFrame) {
+        let (w, h) = (frame.width, frame.height);
+        let (cw, ch) = frame.chroma_size();
+
+        let uploads: Vec<(VideoPlane, u32, u32, GLenum)> = match frame.planes {
+            VideoPlanes::I420 { y, u, v } => vec![
+                (y, w, h, gl::RED),
+                (u, cw, ch, gl::RED),
+                (v, cw, ch, gl::RED),
+            ],
+            VideoPlanes::Nv12 { y, uv } => vec![(y, w, h, gl::RED), (uv, cw, ch, gl::RG)],
+        };
+
+        if self.planes.len() != uploads.len() {
+            self.delete_planes();
+            self.planes = vec![0; uploads.len()];
+            unsafe { gl::GenTextures(self.planes.len() as i32, self.planes.as_mut_ptr()) };
+        }
+
+        // Textures are only reallocated when the resolution or format changes.
+        let layout: Vec<_> = uploads
+            .iter()
+            .map(|&(_, width, height, format)| (width, height, format))
+            .collect();
+        let allocate = layout != self.layout;
+
+        for (&id, (plane, width, height, format)) in self.planes.iter().zip(uploads) {
+            unsafe { upload_plane(id, &plane, width, height, format, allocate) };
+        }
+        self.layout = layout;
+
+        self.nv12 = matches!(frame.planes, VideoPlanes::Nv12 { .. });
+        self.matrix = Mat3::from_cols_array_2d(&frame.conversion_matrix());
+        self.offset = Vec3::from_array(frame.offset());
+    }
+
+    /// Bind the planes to consecutive texture units, starting at `TEXTURE0`.
+    pub fn bind(&self) {
+        unsafe {
+            for (i, &id) in self.planes.iter().enumerate() {
+                gl::ActiveTexture(gl::TEXTURE0 + i as u32);
+                gl::BindTexture(gl::TEXTURE_2D, id);
+            }
+
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+    }
+
+    /// Whether the chroma samples are interleaved in a single texture.
+    pub fn nv12(&self) -> bool {
+        self.nv12
+    }
+
+    /// Matrix converting YUV to RGB, applied after subtracting [`offset`](Self::offset).
+    pub fn matrix(&self) -> &Mat3 {
+        &self.matrix
+    }
+
+    pub fn offset(&self) -> &Vec3 {
+        &self.offset
+    }
+
+    fn delete_planes(&mut self) {
+        if !self.planes.is_empty() {
+            unsafe { gl::DeleteTextures(self.planes.len() as i32, self.planes.as_ptr()) };
+        }
+    }
+}
+
+impl Drop for VideoTexture {
+    fn drop(&mut self) {
+        self.delete_planes();
+    }
+}
+
+/// Upload a plane with one (`RED`) or two (`RG`) bytes per sample, into a
+/// texture of the same size unless `allocate` is set.
+unsafe fn upload_plane(
+    id: GLuint,
+    plane: &VideoPlane,
+    width: u32,
+    height: u32,
+    format: GLenum,
+    allocate: bool,
+) {
+    let (internal_format, bpp) = match format {
+        gl::RG => (gl::RG8, 2),
+        _ => (gl::R8, 1),
+    };
+
+    let row_len = (width * bpp) as usize;
+    let needed = height.saturating_sub(1) as usize * plane.stride as usize + row_len;
+    assert!(
+        plane.stride as usize >= row_len && plane.pixels.len() >= needed,
+        "video plane too small for frame"
+    );
+
+    let (pixels, row_length) = if plane.stride.is_multiple_of(bpp) {
+        (Cow::Borrowed(plane.pixels), (plane.stride / bpp) as i32)
+    } else {
+        let packed = (0..height as usize)
+            .flat_map(|y| {
+                let start = y * plane.stride as usize;
+                &plane.pixels[start..start + row_len]
+            })
+            .copied()
+            .collect();
+
+        (Cow::Owned(packed), 0)
+    };
+
+    gl::BindTexture(gl::TEXTURE_2D, id);
+    gl::PixelStorei(gl::UNPACK_ROW_LENGTH, row_length);
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+
+    if allocate {
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(
+            gl::TEXTURE_2D,
+            gl::TEXTURE_WRAP_S,
+            gl::CLAMP_TO_EDGE as GLint,
+        );
+        gl::TexParameteri(
+            gl::TEXTURE_2D,
+            gl::TEXTURE_WRAP_T,
+            gl::CLAMP_TO_EDGE as GLint,
+        );
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            internal_format as i32,
+            width as i32,
+            height as i32,
+            0,
+            format,
+            gl::UNSIGNED_BYTE,
+            pixels.as_ptr() as *const _,
+        );
+    } else {
+        gl::TexSubImage2D(
+            gl::TEXTURE_2D,
+            0,
+            0,
+            0,
+            width as i32,
+            height as i32,
+            format,
+            gl::UNSIGNED_BYTE,
+            pixels.as_ptr() as *const _,
+        );
+    }
+    gl::PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+
+use resvg::tiny_skia::{Pixmap, Transform};
+use resvg::usvg::{Options, Tree};
+
+use crate::drawer::{Drawer, ImageSource, SubImage};
+use crate::image_data::{ImageData, PixelFormat};
+
+/// Number of rasterized sizes kept around by an [`SvgImage`].
+const CACHED_SIZES: usize = 4;
+
+/// Largest width or height an [`SvgImage`] is rasterized at.
+const MAX_SIZE: u32 = 8192;
+
+#[derive(Debug)]
+pub struct SvgError(resvg::usvg::Error);
+
+impl fmt::Display for SvgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not parse SVG: {}", self.0)
+    }
+}
+
+impl std::error::Error for SvgError {}
+
+/// A vector image rasterized on demand at the size it is drawn at.
+///
+/// Rasterizing at the final pixel size keeps icons crisp at any scale
+/// factor, instead of scaling a bitmap rasterized at a fixed size. The
+/// last few rasterized sizes are cached, so drawing the image at the same
+/// size every frame doesn't rasterize it again.
+///
+/// Requires the `svg` feature.
+pub struct SvgImage {
+    tree: Tree,
+    /// Rasterized images, the most recently used last.
+    cache: Vec<ImageSource>,
+}
+
+impl SvgImage {
+    /// Parse an SVG document, using 96 DPI to resolve physical units.
+    pub fn from_data(data: &[u8]) -> Result<Self, SvgError> {
+        Self::from_data_with_dpi(data, 96.)
+    }
+
+    /// Parse an SVG document, using `dpi` to resolve physical units like
+    /// `mm` or `pt`.
+    pub fn from_data_with_dpi(data: &[u8], dpi: f32) -> Result<Self, SvgError> {
+        let options = Options {
+            dpi,
+            ..Options::default()
+        };
+
+        let tree = Tree::from_data(data, &options).map_err(SvgError)?;
+
+        Ok(Self {
+            tree,
+            cache: Vec::new(),
+        })
+    }
+
+    /// Intrinsic width of the image, in logical pixels.
+    pub fn width(&self) -> f32 {
+        self.tree.size().width()
+    }
+
+    /// Intrinsic height of the image, in logical pixels.
+    pub fn height(&self) -> f32 {
+        self.tree.size().height()
+    }
+
+    /// Get the image rasterized at exactly `width` by `height` pixels.
+    ///
+    /// Sizes larger than 8192 pixels are scaled down to it, keeping the
+    /// aspect ratio, and the image is stretched when drawn at the size it
+    /// was requested at.
+    ///
+    /// The returned image is owned by the `SvgImage`. It is destroyed once
+    /// enough other sizes have been requested, so it should be fetched
+    /// again every frame rather than kept around.
+    pub fn image(&mut self, drawer: &mut dyn Drawer, width: u32, height: u32) -> SubImage {
+        let (width, height) = raster_size(width, height);
+        let cached = self
+            .cache
+            .iter()
+            .position(|image| image.width == width && image.height == height);
+
+        if let Some(index) = cached {
+            let image = self.cache.remove(index);
+            let sub_image = *image;
+            self.cache.push(image);
+            return sub_image;
+        }
+
+        let image = self.rasterize(drawer, width, height);
+
+        if self.cache.len() == CACHED_SIZES {
+            self.cache.remove(0).destroy(drawer);
+        }
+
+        let sub_image = *image;
+        self.cache.push(image);
+        sub_image
+    }
+
+    /// Get the image rasterized at its intrinsic size multiplied by `scale`,
+    /// usually the scale factor of the window.
+    pub fn image_scaled(&mut self, drawer: &mut dyn Drawer, scale: f32) -> SubImage {
+        let width = (self.width() * scale).round() as u32;
+        let height = (self.height() * scale).round() as u32;
+        self.image(drawer, width, height)
+    }
+
+    /// Free all rasterized images right away, instead of at the end of the
+    /// frame as when dropped.
+    pub fn destroy(self, drawer: &mut dyn Drawer) {
+        for image in self.cache {
+            image.destroy(drawer);
+        }
+    }
+
+    fn rasterize(&self, drawer: &mut dyn Drawer, width: u32, height: u32) -> ImageSource {
+        let mut pixmap = Pixmap::new(width, height).unwrap();
+
+        let size = self.tree.size();
+        let transform =
+            Transform::from_scale(width as f32 / size.width(), height as f32 / size.height());
+        resvg::render(&self.tree, transform, &mut pixmap.as_mut());
+
+        let data = ImageData::new(
+            PixelFormat::PremultipliedRgba8,
+            width,
+            height,
+            pixmap.data(),
+        );
+        ImageSource::from_data(drawer, &data)
+    }
+}
+
+/// Size to rasterize at, at least one pixel and at most [`MAX_SIZE`].
+fn raster_size(width: u32, height: u32) -> (u32, u32) {
+    let scale = (MAX_SIZE as f32 / width.max(height) as f32).min(1.);
+    let scaled = |size: u32| ((size as f32 * scale).round() as u32).clamp(1, MAX_SIZE);
+    (scaled(width), scaled(height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rasterizes_at_the_requested_size() {
+        assert_eq!(raster_size(64, 32), (64, 32));
+        assert_eq!(raster_size(0, 0), (1, 1));
+    }
+
+    #[test]
+    fn scales_huge_sizes_down_keeping_the_aspect_ratio() {
+        assert_eq!(
+            raster_size(MAX_SIZE * 4, MAX_SIZE * 2),
+            (MAX_SIZE, MAX_SIZE / 2)
+        );
+        assert_eq!(raster_size(u32::MAX, 1), (MAX_SIZE, 1));
+    }
+}
@@ -0,0 +1,184 @@
+/// A plane of a video frame, with one or two bytes per sample.
+#[derive(Debug, Clone, Copy)]
+pub struct VideoPlane<'a> {
+    pub pixels: &'a [u8],
+    /// Number of bytes between the start of two consecutive rows.
+    pub stride: u32,
+}
+
+impl<'a> VideoPlane<'a> {
+    pub fn new(pixels: &'a [u8], stride: u32) -> Self {
+        Self { pixels, stride }
+    }
+}
+
+/// The planes of a YUV video frame.
+///
+/// Chroma planes are subsampled by two in both directions.
+#[derive(Debug, Clone, Copy)]
+pub enum VideoPlanes<'a> {
+    /// Separate Y, U and V planes.
+    I420 {
+        y: VideoPlane<'a>,
+        u: VideoPlane<'a>,
+        v: VideoPlane<'a>,
+    },
+    /// A Y plane followed by a plane of interleaved U and V samples.
+    Nv12 {
+        y: VideoPlane<'a>,
+        uv: VideoPlane<'a>,
+    },
+}
+
+/// Matrix used to convert YUV samples to RGB.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum YuvMatrix {
+    /// ITU-R BT.601, used by standard definition video.
+    Bt601,
+    /// ITU-R BT.709, used by high definition video.
+    #[default]
+    Bt709,
+}
+
+/// Range of the YUV samples.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum YuvRange {
+    /// Luma goes from 16 to 235 and chroma from 16 to 240.
+    #[default]
+    Limited,
+    /// Luma and chroma use the whole 0 to 255 range.
+    Full,
+}
+
+/// A decoded YUV video frame.
+#[derive(Debug, Clone, Copy)]
+pub struct VideoFrame<'a> {
+    pub width: u32,
+    pub height: u32,
+    pub planes: VideoPlanes<'a>,
+    pub matrix: YuvMatrix,
+    pub range: YuvRange,
+}
+
+impl VideoFrame<'_> {
+    /// Size of the chroma planes.
+    pub fn chroma_size(&self) -> (u32, u32) {
+        (self.width.div_ceil(2), self.height.div_ceil(2))
+    }
+
+    /// Matrix converting `(y, u, v)` samples between 0 and 1, once
+    /// [`offset`](Self::offset) is subtracted, to RGB.
+    ///
+    /// The matrix is given as three columns applied to Y, U and V.
+    pub fn conversion_matrix(&self) -> [[f32; 3]; 3] {
+        let (y_scale, c_scale) = match self.range {
+            YuvRange::Limited => (255. / 219., 255. / 224.),
+            YuvRange::Full => (1., 1.),
+        };
+
+        let (r_v, g_u, g_v, b_u) = match self.matrix {
+            YuvMatrix::Bt601 => (1.402, 0.344_136, 0.714_136, 1.772),
+            YuvMatrix::Bt709 => (1.5748, 0.187_324, 0.468_124, 1.8556),
+        };
+
+        [
+            [y_scale, y_scale, y_scale],
+            [0., -g_u * c_scale, b_u * c_scale],
+            [r_v * c_scale, -g_v * c_scale, 0.],
+        ]
+    }
+
+    /// Value subtracted from `(y, u, v)` samples between 0 and 1 before
+    /// applying the [conversion matrix](Self::conversion_matrix).
+    pub fn offset(&self) -> [f32; 3] {
+        let y_offset = match self.range {
+            YuvRange::Limited => 16. / 255.,
+            YuvRange::Full => 0.,
+        };
+
+        [y_offset, 128. / 255., 128. / 255.]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Convert 8-bit samples to RGB the way the shader does.
+    fn to_rgb(matrix: YuvMatrix, range: YuvRange, yuv: [u8; 3]) -> [f32; 3] {
+        let frame = VideoFrame {
+            width: 2,
+            height: 2,
+            planes: VideoPlanes::Nv12 {
+                y: VideoPlane::new(&[], 2),
+                uv: VideoPlane::new(&[], 2),
+            },
+            matrix,
+            range,
+        };
+
+        let m = frame.conversion_matrix();
+        let offset = frame.offset();
+        let v: [f32; 3] = std::array::from_fn(|i| yuv[i] as f32 / 255. - offset[i]);
+        std::array::from_fn(|row| (0..3).map(|col| m[col][row] * v[col]).sum())
+    }
+
+    fn assert_rgb(rgb: [f32; 3], expected: [f32; 3]) {
+        for (c, e) in rgb.into_iter().zip(expected) {
+            assert!((c - e).abs() < 0.01, "{rgb:?} is not {expected:?}");
+        }
+    }
+
+    #[test]
+    fn maps_the_luma_range_to_black_and_white() {
+        for matrix in [YuvMatrix::Bt601, YuvMatrix::Bt709] {
+            assert_rgb(to_rgb(matrix, YuvRange::Full, [0, 128, 128]), [0., 0., 0.]);
+            assert_rgb(
+                to_rgb(matrix, YuvRange::Full, [255, 128, 128]),
+                [1., 1., 1.],
+            );
+            assert_rgb(
+                to_rgb(matrix, YuvRange::Limited, [16, 128, 128]),
+                [0., 0., 0.],
+            );
+            assert_rgb(
+                to_rgb(matrix, YuvRange::Limited, [235, 128, 128]),
+                [1., 1., 1.],
+            );
+        }
+    }
+
+    #[test]
+    fn converts_primaries() {
+        // Full range BT.601 red, as encoded by JPEG.
+        assert_rgb(
+            to_rgb(YuvMatrix::Bt601, YuvRange::Full, [76, 85, 255]),
+            [1., 0., 0.],
+        );
+        // Limited range BT.709 red.
+        assert_rgb(
+            to_rgb(YuvMatrix::Bt709, YuvRange::Limited, [63, 102, 240]),
+            [1., 0., 0.],
+        );
+        // Limited range BT.709 blue.
+        assert_rgb(
+            to_rgb(YuvMatrix::Bt709, YuvRange::Limited, [32, 240, 118]),
+            [0., 0., 1.],
+        );
+    }
+
+    #[test]
+    fn rounds_chroma_sizes_up() {
+        let frame = VideoFrame {
+            width: 5,
+            height: 3,
+            planes: VideoPlanes::Nv12 {
+                y: VideoPlane::new(&[], 5),
+                uv: VideoPlane::new(&[], 6),
+            },
+            matrix: YuvMatrix::Bt709,
+            range: YuvRange::Limited,
+        };
+        assert_eq!(frame.chroma_size(), (3, 2));
+    }
+}